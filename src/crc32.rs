@@ -0,0 +1,47 @@
+use super::*;
+
+/// CRC-32 checksum as used by Ethernet and zlib (IEEE 802.3, reflected polynomial 0xEDB88320).
+///
+/// Computed bitwise instead of with a lookup table, to keep the flash footprint small.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    /// Creates a checksum over no data yet.
+    pub const fn new() -> Self {
+        Self { state: 0xFFFF_FFFF }
+    }
+
+    /// Returns the checksum of the data fed so far.
+    pub const fn finalize(&self) -> u32 {
+        !self.state
+    }
+
+    /// Returns the checksum of the provided data.
+    pub fn checksum(data: &[u8]) -> u32 {
+        let mut crc = Self::new();
+        crc.update(data);
+        crc.finalize()
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Digest for Crc32 {
+    fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.state ^= byte as u32;
+            for _ in 0..8 {
+                let mask = (self.state & 1).wrapping_neg();
+                self.state = (self.state >> 1) ^ (0xEDB8_8320 & mask);
+            }
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use super::*;
+use core::fmt::Debug;
+use embedded_hal::digital::OutputPin;
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::spi::SpiDevice;
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+/// Wraps the driver so its `NorFlash` implementation erases in units of `ERASE_SIZE` bytes instead of sectors.
+///
+/// `ERASE_SIZE` must be SECTOR_SIZE, BLOCK_32K_SIZE or BLOCK_64K_SIZE. Erases use the matching sector or
+/// block erase command. Some stacks perform better with the larger erase granularity of the block erases.
+pub struct EraseGranularity<FLASH, const ERASE_SIZE: u32> {
+    flash: FLASH,
+}
+
+impl<FLASH, const ERASE_SIZE: u32> EraseGranularity<FLASH, ERASE_SIZE> {
+    /// Wraps the driver.
+    pub fn new(flash: FLASH) -> Self {
+        const {
+            assert!(
+                ERASE_SIZE == SECTOR_SIZE
+                    || ERASE_SIZE == BLOCK_32K_SIZE
+                    || ERASE_SIZE == BLOCK_64K_SIZE,
+                "ERASE_SIZE must be SECTOR_SIZE, BLOCK_32K_SIZE or BLOCK_64K_SIZE"
+            )
+        };
+
+        Self { flash }
+    }
+
+    /// Returns a mutable reference to the wrapped driver.
+    pub fn inner(&mut self) -> &mut FLASH {
+        &mut self.flash
+    }
+
+    /// Releases the wrapped driver.
+    pub fn into_inner(self) -> FLASH {
+        self.flash
+    }
+}
+
+impl<SPI, S: Debug, P: Debug, HOLD, WP, DELAY, const ERASE_SIZE: u32> ErrorType
+    for EraseGranularity<W25q256jv<SPI, HOLD, WP, DELAY>, ERASE_SIZE>
+where
+    SPI: SpiDevice<Error = S>,
+    HOLD: OutputPin<Error = P>,
+    WP: OutputPin<Error = P>,
+    S: Debug,
+    P: Debug,
+{
+    type Error = Error<S, P>;
+}
+
+impl<SPI, S: Debug, P: Debug, HOLD, WP, DELAY, const ERASE_SIZE: u32> ReadNorFlash
+    for EraseGranularity<W25q256jv<SPI, HOLD, WP, DELAY>, ERASE_SIZE>
+where
+    SPI: SpiDevice<Error = S>,
+    HOLD: OutputPin<Error = P>,
+    WP: OutputPin<Error = P>,
+    DELAY: DelayNs,
+    S: Debug,
+    P: Debug,
+{
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.flash.read(offset, bytes).await
+    }
+
+    fn capacity(&self) -> usize {
+        CAPACITY as usize
+    }
+}
+
+impl<SPI, S: Debug, P: Debug, HOLD, WP, DELAY, const ERASE_SIZE: u32> NorFlash
+    for EraseGranularity<W25q256jv<SPI, HOLD, WP, DELAY>, ERASE_SIZE>
+where
+    SPI: SpiDevice<Error = S>,
+    HOLD: OutputPin<Error = P>,
+    WP: OutputPin<Error = P>,
+    DELAY: DelayNs,
+    S: Debug,
+    P: Debug,
+{
+    const WRITE_SIZE: usize = 1;
+
+    const ERASE_SIZE: usize = ERASE_SIZE as usize;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(from, to, ERASE_SIZE)?;
+
+        for index in from / ERASE_SIZE..to / ERASE_SIZE {
+            match ERASE_SIZE {
+                SECTOR_SIZE => self.flash.erase_sector(index).await?,
+                BLOCK_32K_SIZE => self.flash.erase_block_32k(index).await?,
+                _ => self.flash.erase_block_64k(index).await?,
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        // Like the driver's NorFlash implementation, so its write settings, e.g. auto-erase, apply
+        self.flash.write_nor_flash(offset, bytes).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+    use embassy_futures::block_on;
+    use embedded_storage_async::nor_flash::NorFlashError;
+
+    #[test]
+    fn erase_with_64k_blocks() {
+        let (sim, flash) = mock::flash();
+        let mut flash = EraseGranularity::<_, BLOCK_64K_SIZE>::new(flash);
+        assert_eq!(
+            <EraseGranularity<mock::Flash, BLOCK_64K_SIZE> as NorFlash>::ERASE_SIZE,
+            BLOCK_64K_SIZE as usize
+        );
+
+        block_on(NorFlash::erase(
+            &mut flash,
+            BLOCK_64K_SIZE,
+            3 * BLOCK_64K_SIZE,
+        ))
+        .unwrap();
+
+        let chip = sim.borrow();
+        assert_eq!(
+            chip.commands(0xDC),
+            [
+                [0xDC, 0x00, 0x01, 0x00, 0x00],
+                [0xDC, 0x00, 0x02, 0x00, 0x00]
+            ]
+        );
+        assert_eq!(chip.count(0x21), 0);
+        assert_eq!(chip.count(0x5C), 0);
+    }
+
+    #[test]
+    fn erase_with_32k_blocks() {
+        let (sim, flash) = mock::flash();
+        let mut flash = EraseGranularity::<_, BLOCK_32K_SIZE>::new(flash);
+
+        block_on(NorFlash::erase(&mut flash, 0, BLOCK_32K_SIZE)).unwrap();
+
+        assert_eq!(sim.borrow().commands(0x5C), [[0x5C, 0, 0, 0, 0]]);
+    }
+
+    #[test]
+    fn erase_rejects_ranges_not_aligned_to_the_erase_size() {
+        let (sim, flash) = mock::flash();
+        let mut flash = EraseGranularity::<_, BLOCK_64K_SIZE>::new(flash);
+
+        let error = block_on(NorFlash::erase(&mut flash, 0, SECTOR_SIZE)).unwrap_err();
+        assert_eq!(error.kind(), NorFlashErrorKind::NotAligned);
+        assert_eq!(sim.borrow().transactions(), 0);
+    }
+
+    #[test]
+    fn write_applies_the_auto_erase_of_the_driver() {
+        let (sim, mut flash) = mock::flash();
+        flash.set_auto_erase_on_write(true);
+        sim.borrow_mut().set_memory(SECTOR_SIZE, &[0x0F; 8]);
+        let mut flash = EraseGranularity::<_, BLOCK_64K_SIZE>::new(flash);
+
+        block_on(NorFlash::write(&mut flash, SECTOR_SIZE + 4, &[0xF0; 4])).unwrap();
+
+        let chip = sim.borrow();
+        assert_eq!(chip.count(0x21), 1);
+        assert_eq!(chip.memory(SECTOR_SIZE, 4), [0x0F; 4]);
+        assert_eq!(chip.memory(SECTOR_SIZE + 4, 4), [0xF0; 4]);
+    }
+}
@@ -0,0 +1,215 @@
+use super::*;
+use core::fmt::Debug;
+use embedded_hal::digital::OutputPin;
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::spi::SpiDevice;
+
+/// Streams an image larger than RAM into the flash chip, e.g. a firmware update received over the air.
+///
+/// Data is buffered in chunks of `PAGES` pages. Sectors are erased just before the first page in them is programmed,
+/// so only the sectors the image actually covers are erased.
+///
+/// A larger buffer costs RAM, but hands larger writes to the driver and checks for sector starts less often.
+/// The chip is still programmed page by page.
+pub struct FirmwareWriter<'a, SPI, HOLD, WP, DELAY = NoDelay, const PAGES: usize = 1> {
+    flash: &'a mut W25q256jv<SPI, HOLD, WP, DELAY>,
+    /// Address the buffered pages will be programmed to.
+    address: u32,
+    buf: [[u8; PAGE_SIZE as usize]; PAGES],
+    buffered: usize,
+    written: u32,
+}
+
+impl<'a, SPI, S: Debug, P: Debug, HOLD, WP, DELAY, const PAGES: usize>
+    FirmwareWriter<'a, SPI, HOLD, WP, DELAY, PAGES>
+where
+    SPI: SpiDevice<Error = S>,
+    HOLD: OutputPin<Error = P>,
+    WP: OutputPin<Error = P>,
+    DELAY: DelayNs,
+    S: Debug,
+    P: Debug,
+{
+    /// Creates a writer that writes the image starting at the provided address.
+    ///
+    /// # Arguments
+    /// * `flash` - The flash chip to write to.
+    /// * `start` - Address where the image starts. Must be a multiple of SECTOR_SIZE.
+    pub fn new(
+        flash: &'a mut W25q256jv<SPI, HOLD, WP, DELAY>,
+        start: u32,
+    ) -> Result<Self, Error<S, P>> {
+        const { assert!(PAGES > 0, "the buffer must hold at least one page") };
+
+        if !start.is_multiple_of(SECTOR_SIZE) {
+            return Err(Error::NotAligned);
+        }
+
+        if start >= CAPACITY {
+            return Err(Error::OutOfBounds);
+        }
+
+        Ok(Self {
+            flash,
+            address: start,
+            buf: [[0; PAGE_SIZE as usize]; PAGES],
+            buffered: 0,
+            written: 0,
+        })
+    }
+
+    /// Appends data to the image. Whenever the buffer fills up, it is programmed, erasing the sectors
+    /// that start within it first.
+    pub async fn push(&mut self, mut data: &[u8]) -> Result<(), Error<S, P>> {
+        while !data.is_empty() {
+            let buf = self.buf.as_flattened_mut();
+            let len = core::cmp::min(buf.len() - self.buffered, data.len());
+            buf[self.buffered..self.buffered + len].copy_from_slice(&data[..len]);
+            self.buffered += len;
+            data = &data[len..];
+
+            if self.buffered == buf.len() {
+                self.flush().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Programs the remaining partially filled buffer, if any.
+    ///
+    /// Returns the total number of bytes written.
+    pub async fn finish(mut self) -> Result<u32, Error<S, P>> {
+        self.flush().await?;
+
+        Ok(self.written)
+    }
+
+    /// Programs the buffered data to the current pages.
+    async fn flush(&mut self) -> Result<(), Error<S, P>> {
+        if self.buffered == 0 {
+            return Ok(());
+        }
+
+        for page in (self.address..self.address + self.buffered as u32).step_by(PAGE_SIZE as usize)
+        {
+            if page.is_multiple_of(SECTOR_SIZE) {
+                self.flash.erase_sector(page / SECTOR_SIZE).await?;
+            }
+        }
+
+        self.flash
+            .write(self.address, &self.buf.as_flattened()[..self.buffered])
+            .await?;
+
+        self.address += (PAGE_SIZE as usize * PAGES) as u32;
+        self.written += self.buffered as u32;
+        self.buffered = 0;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::mock;
+    use embassy_futures::block_on;
+    use std::vec::Vec;
+
+    const START: u32 = 16 * SECTOR_SIZE;
+
+    fn image(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 256) as u8).collect()
+    }
+
+    /// Checks that every sector is erased before its first page is programmed, and returns the erased sectors.
+    fn erased_before_programmed(sim: &mock::Sim) -> Vec<u32> {
+        let mut erased = Vec::new();
+
+        for frame in sim.borrow().frames() {
+            let address = || u32::from_be_bytes([frame[1], frame[2], frame[3], frame[4]]);
+            match frame[0] {
+                0x21 => erased.push(address() / SECTOR_SIZE),
+                0x12 => assert!(erased.contains(&(address() / SECTOR_SIZE))),
+                _ => {}
+            }
+        }
+
+        erased
+    }
+
+    #[test]
+    fn push_across_sector_boundaries() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut()
+            .set_memory(START, &[0x00; 4 * SECTOR_SIZE as usize]);
+        let data = image(2 * SECTOR_SIZE as usize + 1000);
+
+        let mut writer = FirmwareWriter::<_, _, _>::new(&mut flash, START).unwrap();
+        for chunk in data.chunks(100) {
+            block_on(writer.push(chunk)).unwrap();
+        }
+        assert_eq!(block_on(writer.finish()).unwrap(), data.len() as u32);
+
+        assert_eq!(erased_before_programmed(&sim), [16, 17, 18]);
+        let chip = sim.borrow();
+        assert_eq!(chip.memory(START, data.len()), data);
+        // The rest of the last sector is erased, the sector after it is untouched
+        assert_eq!(chip.memory(START + data.len() as u32, 10), [0xFF; 10]);
+        assert_eq!(chip.memory(START + 3 * SECTOR_SIZE, 10), [0x00; 10]);
+    }
+
+    #[test]
+    fn push_with_a_multi_page_buffer_flushes_the_partial_page() {
+        let (sim, mut flash) = mock::flash();
+        let data = image(SECTOR_SIZE as usize + 3 * PAGE_SIZE as usize + 17);
+
+        let mut writer = FirmwareWriter::<_, _, _, _, 4>::new(&mut flash, START).unwrap();
+        block_on(writer.push(&data)).unwrap();
+        assert_eq!(block_on(writer.finish()).unwrap(), data.len() as u32);
+
+        assert_eq!(erased_before_programmed(&sim), [16, 17]);
+        assert_eq!(sim.borrow().memory(START, data.len()), data);
+        assert_eq!(
+            *sim.borrow().program_addresses().last().unwrap(),
+            START + SECTOR_SIZE + 3 * PAGE_SIZE
+        );
+    }
+
+    #[test]
+    fn new_rejects_unaligned_start() {
+        let (_, mut flash) = mock::flash();
+
+        assert!(matches!(
+            FirmwareWriter::<_, _, _>::new(&mut flash, START + PAGE_SIZE),
+            Err(Error::NotAligned)
+        ));
+        assert!(matches!(
+            FirmwareWriter::<_, _, _>::new(&mut flash, CAPACITY),
+            Err(Error::OutOfBounds)
+        ));
+    }
+
+    #[test]
+    fn push_with_a_multi_page_buffer_programs_page_by_page() {
+        let (sim, mut flash) = mock::flash();
+        let data = image(2 * SECTOR_SIZE as usize);
+
+        let mut writer = FirmwareWriter::<_, _, _, _, 4>::new(&mut flash, START).unwrap();
+        for chunk in data.chunks(300) {
+            block_on(writer.push(chunk)).unwrap();
+        }
+        block_on(writer.finish()).unwrap();
+
+        assert_eq!(erased_before_programmed(&sim), [16, 17]);
+        let chip = sim.borrow();
+        let expected: Vec<u32> = (0..2 * SECTOR_SIZE / PAGE_SIZE)
+            .map(|page| START + page * PAGE_SIZE)
+            .collect();
+        assert_eq!(chip.program_addresses(), expected);
+        assert_eq!(chip.memory(START, data.len()), data);
+    }
+}
@@ -1,10 +1,22 @@
 #![no_std]
 
+#[cfg(feature = "std")]
+extern crate std;
+
 use core::fmt::Debug;
 use embedded_hal::digital::{OutputPin, PinState};
 use embedded_hal::spi::SpiDevice;
+use embedded_hal_async::delay::DelayNs;
 use embedded_storage_async::nor_flash::{ErrorType, NorFlashError, NorFlashErrorKind};
 
+pub mod crc32;
+pub mod erase_granularity;
+pub mod firmware_writer;
+pub mod log_store;
+#[cfg(test)]
+mod mock;
+pub mod ping_pong;
+pub mod suspend;
 pub mod w25q256jv;
 
 pub const PAGE_SIZE: u32 = 256;
@@ -16,15 +28,579 @@ pub const BLOCK_32K_SIZE: u32 = SECTOR_SIZE * 8;
 pub const N_BLOCKS_32K: u32 = N_SECTORS / 8;
 pub const BLOCK_64K_SIZE: u32 = BLOCK_32K_SIZE * 2;
 pub const N_BLOCKS_64K: u32 = N_BLOCKS_32K / 2;
+/// Typical standby current in microamps, from the datasheet. Useful for power budgeting.
+pub const STANDBY_CURRENT_UA: u32 = 10;
+/// Typical current in deep power-down in microamps, from the datasheet.
+pub const DEEP_POWER_DOWN_CURRENT_UA: u32 = 1;
+/// Typical current while programming a page in milliamps, from the datasheet.
+pub const PROGRAM_CURRENT_MA: u32 = 20;
+/// Typical current while erasing in milliamps, from the datasheet.
+pub const ERASE_CURRENT_MA: u32 = 20;
+/// Minimum number of erase cycles each sector is rated for, from the datasheet.
+pub const ERASE_CYCLES: u32 = 100_000;
+/// JEDEC ID of the W25Q256JV: the manufacturer ID, memory type and capacity.
+pub const JEDEC_ID: [u8; 3] = [0xEF, 0x40, 0x19];
+/// Legacy device ID of the W25Q256JV, as returned by [W25q256jv::wake_and_identify].
+pub const DEVICE_ID: u8 = 0x18;
+/// Number of bytes in front of the data in a [W25q256jv::read_single_transfer] frame.
+pub const READ_COMMAND_LEN: usize = 5;
+/// Length of the little-endian length prefix in front of a record. See [W25q256jv::write_record].
+pub const RECORD_HEADER_LEN: u32 = 2;
+/// Size of each of the three security registers in bytes.
+pub const SECURITY_REGISTER_SIZE: u32 = 256;
+
+/// Returns whether the range of `len` bytes starting at `address` lies within a single sector.
+/// An empty range always does.
+pub const fn within_single_sector(address: u32, len: u32) -> bool {
+    if len == 0 {
+        return true;
+    }
+
+    let last = address as u64 + len as u64 - 1;
+    address as u64 / SECTOR_SIZE as u64 == last / SECTOR_SIZE as u64
+}
+
+/// Returns the number of sectors the byte range `[start, end)` touches, e.g. to estimate the time to erase it.
+/// Partially covered sectors at either end count as well. An empty range touches no sectors.
+pub const fn sector_count(start: u32, end: u32) -> u32 {
+    if end <= start {
+        return 0;
+    }
+
+    end.div_ceil(SECTOR_SIZE) - start / SECTOR_SIZE
+}
+
+/// Describes how a write maps onto the chip: the sectors it touches, which have to be erased for an overwrite,
+/// and the pages it is programmed in. See [plan_write].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WritePlan {
+    /// Address of the first byte of the write.
+    pub address: u32,
+    /// Length of the write in bytes.
+    pub len: u32,
+    /// Index of the first sector the write touches.
+    pub first_sector: u32,
+    /// Number of sectors the write touches.
+    pub sector_count: u32,
+    /// Index of the first page the write touches.
+    pub first_page: u32,
+    /// Number of pages the write touches, i.e. the number of page programs.
+    pub page_count: u32,
+}
+
+impl WritePlan {
+    /// Returns the address and length of each page program of the write, in order.
+    /// Only the first and last program may be shorter than a page.
+    pub fn programs(&self) -> impl Iterator<Item = (u32, u32)> {
+        let (address, end) = (self.address, self.address + self.len);
+
+        (self.first_page..self.first_page + self.page_count).map(move |page| {
+            let start = core::cmp::max(page * PAGE_SIZE, address);
+            let page_end = core::cmp::min((page as u64 + 1) * PAGE_SIZE as u64, end as u64) as u32;
+            (start, page_end - start)
+        })
+    }
+}
+
+/// Plans a write of `len` bytes at `address` without executing anything, e.g. to inspect or schedule
+/// the erases and programs of an update ahead of time. The range is not checked against the capacity of the chip.
+pub const fn plan_write(address: u32, len: u32) -> WritePlan {
+    let end = address.saturating_add(len);
+
+    WritePlan {
+        address,
+        len: end - address,
+        first_sector: address / SECTOR_SIZE,
+        sector_count: sector_count(address, end),
+        first_page: address / PAGE_SIZE,
+        page_count: if end <= address {
+            0
+        } else {
+            end.div_ceil(PAGE_SIZE) - address / PAGE_SIZE
+        },
+    }
+}
+
+/// Returns the index of the 64KB block that contains the provided address.
+pub const fn block_64k_index(address: u32) -> u32 {
+    address / BLOCK_64K_SIZE
+}
+
+/// Returns the address of the first byte of the 64KB block with the provided index.
+pub const fn block_64k_base(index: u32) -> u32 {
+    index * BLOCK_64K_SIZE
+}
+
+/// The units the chip can erase, apart from the whole chip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EraseUnit {
+    /// A sector of SECTOR_SIZE bytes, see [W25q256jv::erase_sector].
+    Sector,
+    /// A block of BLOCK_32K_SIZE bytes, see [W25q256jv::erase_block_32k].
+    Block32K,
+    /// A block of BLOCK_64K_SIZE bytes, see [W25q256jv::erase_block_64k].
+    Block64K,
+}
+
+impl EraseUnit {
+    /// Returns the size of the unit in bytes.
+    pub const fn size(self) -> u32 {
+        match self {
+            EraseUnit::Sector => SECTOR_SIZE,
+            EraseUnit::Block32K => BLOCK_32K_SIZE,
+            EraseUnit::Block64K => BLOCK_64K_SIZE,
+        }
+    }
+}
+
+/// Returns the largest unit that can be erased starting at the provided address, and its size in bytes.
+/// Addresses that aren't sector-aligned can't be erased, but still return the sector.
+pub const fn largest_aligned_block(address: u32) -> (u32, EraseUnit) {
+    let unit = if address.is_multiple_of(BLOCK_64K_SIZE) {
+        EraseUnit::Block64K
+    } else if address.is_multiple_of(BLOCK_32K_SIZE) {
+        EraseUnit::Block32K
+    } else {
+        EraseUnit::Sector
+    };
+
+    (unit.size(), unit)
+}
+
+/// Returns how many of the rated [ERASE_CYCLES] a sector has left after being erased `erase_count` times.
+/// The driver doesn't count erases itself, so the count has to be tracked by the caller, e.g. in a wear-leveling layer.
+pub const fn remaining_erase_cycles(erase_count: u32) -> u32 {
+    ERASE_CYCLES.saturating_sub(erase_count)
+}
+
+/// Number of SPI transactions of the write enable, its confirmation, the command and the completion poll,
+/// which every page program and every erase issues.
+const TRANSACTIONS_PER_OPERATION: usize = 4;
+
+/// Returns how many SPI transactions [W25q256jv::write] issues for writing `len` bytes at `address`, e.g. to reason
+/// about bus bandwidth. Page splits are accounted for, as well as the readback of the `verify-writes` feature.
+///
+/// This is the minimum: it assumes every operation completes on the first status poll, and doesn't apply
+/// to the fast write mode, which leaves out one transaction per page.
+pub const fn transactions_for_write(address: u32, len: u32) -> usize {
+    // Wait for a previous operation to complete
+    let mut transactions = 1;
+    let mut address = address;
+    let end = address as u64 + len as u64;
+
+    // Even an empty write programs an empty page
+    loop {
+        let page_end = (address as u64 / PAGE_SIZE as u64 + 1) * PAGE_SIZE as u64;
+        let chunk_end = if page_end < end { page_end } else { end };
+        let chunk_len = (chunk_end - address as u64) as usize;
+
+        transactions += TRANSACTIONS_PER_OPERATION;
+        if cfg!(feature = "verify-writes") {
+            transactions += chunk_len.div_ceil(64);
+        }
+
+        if chunk_end >= end {
+            return transactions;
+        }
+        address = chunk_end as u32;
+    }
+}
+
+/// Returns how many SPI transactions [W25q256jv::erase_range] issues for erasing the sectors from `start_address`
+/// up to `end_address`, accounting for the readback of the `verify-erases` feature.
+///
+/// This is the minimum: it assumes every erase completes on the first status poll and passes verification.
+/// Like [transactions_for_write], it assumes the default settings of the driver: the pacing of the status polls
+/// (see [W25q256jv::set_timing_profile]) only adds polls, and the fast write mode (see [W25q256jv::set_fast_write])
+/// leaves out one transaction per sector.
+pub const fn transactions_for_erase_range(start_address: u32, end_address: u32) -> usize {
+    let sectors = (end_address.saturating_sub(start_address) / SECTOR_SIZE) as usize;
+
+    // Every sector erase first waits for a previous operation to complete
+    let mut per_sector = 1 + TRANSACTIONS_PER_OPERATION;
+    if cfg!(feature = "verify-erases") {
+        per_sector += SECTOR_SIZE as usize / 64;
+    }
+
+    sectors * per_sector
+}
+
+/// Density of a Winbond W25Q-JV part, as reported in the capacity byte of the JEDEC ID. See [JedecId::density].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Density {
+    /// 64 Mbit (8 MiB), e.g. the W25Q64JV.
+    Mbit64,
+    /// 128 Mbit (16 MiB), e.g. the W25Q128JV.
+    Mbit128,
+    /// 256 Mbit (32 MiB), the W25Q256JV.
+    Mbit256,
+}
+
+impl Density {
+    /// Get the capacity of a part with this density in bytes.
+    pub const fn capacity(self) -> u32 {
+        match self {
+            Density::Mbit64 => 8 * 1024 * 1024,
+            Density::Mbit128 => 16 * 1024 * 1024,
+            Density::Mbit256 => 32 * 1024 * 1024,
+        }
+    }
+}
+
+impl TryFrom<u8> for Density {
+    /// The unrecognized density byte.
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x17 => Ok(Density::Mbit64),
+            0x18 => Ok(Density::Mbit128),
+            0x19 => Ok(Density::Mbit256),
+            other => Err(other),
+        }
+    }
+}
+
+impl From<Density> for u8 {
+    fn from(density: Density) -> Self {
+        match density {
+            Density::Mbit64 => 0x17,
+            Density::Mbit128 => 0x18,
+            Density::Mbit256 => 0x19,
+        }
+    }
+}
+
+/// Function of the /HOLD or /RESET pin (IO3), selected by the HOLD/RST bit in status register 3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HoldResetMode {
+    /// The pin pauses communication while low. This is the factory default.
+    Hold,
+    /// The pin resets the chip while low.
+    Reset,
+}
+
+/// Identification of the chip as reported by the Read JEDEC ID command. See [W25q256jv::read_jedec_id].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct JedecId {
+    /// The manufacturer ID, 0xEF for Winbond.
+    pub manufacturer_id: u8,
+    /// The memory type, 0x40 for the W25Q256JV.
+    pub memory_type: u8,
+    /// The capacity, 0x19 for the W25Q256JV. See [Density].
+    pub capacity: u8,
+}
+
+impl JedecId {
+    /// Decodes the capacity byte, e.g. to tell the parts of the family apart.
+    /// Returns the byte itself if it isn't a known density.
+    pub fn density(&self) -> Result<Density, u8> {
+        Density::try_from(self.capacity)
+    }
+
+    /// Returns true if the ID is the one of the W25Q256JV, see [JEDEC_ID].
+    pub fn is_w25q256jv(&self) -> bool {
+        self.manufacturer_id == JEDEC_ID[0]
+            && self.memory_type == JEDEC_ID[1]
+            && self.density() == Ok(Density::Mbit256)
+    }
+}
+
+impl From<[u8; 3]> for JedecId {
+    fn from(id: [u8; 3]) -> Self {
+        Self {
+            manufacturer_id: id[0],
+            memory_type: id[1],
+            capacity: id[2],
+        }
+    }
+}
+
+impl From<JedecId> for [u8; 3] {
+    fn from(id: JedecId) -> Self {
+        [id.manufacturer_id, id.memory_type, id.capacity]
+    }
+}
+
+/// Block protection bits of status register 1, which select the region the chip refuses to program or erase.
+/// The region they select is inverted when the CMP bit in status register 2 is set, see [W25q256jv::complement_protect].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BlockProtection {
+    /// The block protect bits BP0 to BP3. 0 protects nothing, unless CMP is set.
+    pub bp: u8,
+    /// The top/bottom bit TB. If set, the region is counted from the bottom of the memory, otherwise from the top.
+    pub from_bottom: bool,
+    /// The complement protect bit CMP of status register 2. If set, the region selected by the other bits is inverted.
+    pub complement: bool,
+}
+
+impl BlockProtection {
+    /// Decodes the values of status registers 1 and 2.
+    pub const fn from_status_registers(sr1: u8, sr2: u8) -> Self {
+        Self {
+            bp: (sr1 >> 2) & 0x0F,
+            from_bottom: sr1 & 0x40 != 0,
+            complement: sr2 & 0x40 != 0,
+        }
+    }
+
+    /// Returns the range of addresses the chip refuses to program or erase, per the block protection table
+    /// of the datasheet. Assumes the individual block protection of the WPS bit is off, which is the default.
+    pub const fn protected_range(&self) -> core::ops::Range<u32> {
+        // Each step doubles the region, starting at one 64KB block, until it covers the whole chip
+        let len = match self.bp {
+            0 => 0,
+            bp if bp < 10 => BLOCK_64K_SIZE << (bp - 1),
+            _ => CAPACITY,
+        };
+
+        // The complement of a region at one end is the rest of the chip, counted from the other end
+        let (len, from_bottom) = if self.complement {
+            (CAPACITY - len, !self.from_bottom)
+        } else {
+            (len, self.from_bottom)
+        };
+
+        if from_bottom {
+            0..len
+        } else {
+            CAPACITY - len..CAPACITY
+        }
+    }
+}
+
+/// The state of the chip relevant to starting a program or erase, decoded from status registers 1 and 2.
+/// See [W25q256jv::write_readiness].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WriteReadiness {
+    /// The chip is busy with an operation and ignores most commands.
+    pub busy: bool,
+    /// The write-enable latch is set.
+    pub write_enabled: bool,
+    /// The region that is protected from programs and erases.
+    pub protected_region: BlockProtection,
+}
+
+impl WriteReadiness {
+    /// Decodes the values of status registers 1 and 2.
+    pub const fn from_status_registers(sr1: u8, sr2: u8) -> Self {
+        Self {
+            busy: sr1 & 0x01 != 0,
+            write_enabled: sr1 & 0x02 != 0,
+            protected_region: BlockProtection::from_status_registers(sr1, sr2),
+        }
+    }
+}
+
+/// Options for [W25q256jv::init].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InitConfig {
+    /// Reset the continuous read mode a previous firmware may have left the chip in. Defaults to true.
+    pub exit_continuous_read: bool,
+    /// Put the chip into 4-byte address mode. Defaults to false, as the driver only uses commands that take
+    /// a 4-byte address regardless of the address mode.
+    pub enter_4_byte_address_mode: bool,
+    /// Clear the non-volatile Quad Enable bit if a previous firmware left it set. Defaults to false, as
+    /// this writes status register 2. The driver only uses single-line SPI, which works regardless of the bit,
+    /// but with the bit set the WP and HOLD pins lose their function.
+    pub clear_quad_enable: bool,
+}
+
+impl Default for InitConfig {
+    fn default() -> Self {
+        Self {
+            exit_continuous_read: true,
+            enter_4_byte_address_mode: false,
+            clear_quad_enable: false,
+        }
+    }
+}
+
+/// Typical durations of the operations of the chip.
+///
+/// While the chip is busy with an operation, the driver polls the status at a fraction of the typical duration,
+/// if a delay source is configured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TimingProfile {
+    pub page_program_us: u32,
+    pub sector_erase_us: u32,
+    pub block_32k_erase_us: u32,
+    pub block_64k_erase_us: u32,
+    pub chip_erase_us: u32,
+}
+
+impl TimingProfile {
+    /// Typical times from the W25Q256JV datasheet.
+    pub const DATASHEET: Self = Self {
+        page_program_us: 700,
+        sector_erase_us: 50_000,
+        block_32k_erase_us: 120_000,
+        block_64k_erase_us: 150_000,
+        chip_erase_us: 80_000_000,
+    };
+
+    /// The number of times the status is polled during a typical operation.
+    pub const POLLS_PER_OPERATION: u32 = 10;
+
+    /// Derives the typical times from the JEDEC Basic Flash Parameter Table (JESD216) of the SFDP table.
+    /// Times the table doesn't declare keep their datasheet values.
+    pub fn from_basic_parameter_table(table: &[u8]) -> Self {
+        let dword = |n: usize| {
+            table
+                .get((n - 1) * 4..n * 4)
+                .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+
+        let mut profile = Self::DATASHEET;
+
+        // The erase types are declared as size exponent and opcode in DWORDs 8 and 9,
+        // their typical times in DWORD 10.
+        if let (Some(types_1_2), Some(types_3_4), Some(times)) = (dword(8), dword(9), dword(10)) {
+            let sizes =
+                [types_1_2, types_1_2 >> 16, types_3_4, types_3_4 >> 16].map(|size| size & 0xFF);
+
+            for (erase_type, size) in sizes.into_iter().enumerate() {
+                let time = times >> (4 + erase_type * 7);
+                let count = (time & 0x1F) + 1;
+                let unit_us = match (time >> 5) & 0x03 {
+                    0b00 => 1_000,
+                    0b01 => 16_000,
+                    0b10 => 128_000,
+                    _ => 1_000_000,
+                };
+
+                match size {
+                    12 => profile.sector_erase_us = count * unit_us,
+                    15 => profile.block_32k_erase_us = count * unit_us,
+                    16 => profile.block_64k_erase_us = count * unit_us,
+                    _ => {}
+                }
+            }
+        }
+
+        // Page program and chip erase times are declared in DWORD 11
+        if let Some(times) = dword(11) {
+            let count = ((times >> 8) & 0x1F) + 1;
+            let unit_us = if times & (1 << 13) == 0 { 8 } else { 64 };
+            profile.page_program_us = count * unit_us;
+
+            let count = ((times >> 24) & 0x1F) + 1;
+            let unit_us: u32 = match (times >> 29) & 0x03 {
+                0b00 => 16_000,
+                0b01 => 256_000,
+                0b10 => 4_000_000,
+                _ => 64_000_000,
+            };
+            profile.chip_erase_us = count.saturating_mul(unit_us);
+        }
+
+        profile
+    }
+}
+
+/// Statistics of the operations performed by the driver, for estimating the wear of the chip.
+/// Only available with the `stats` feature.
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Stats {
+    /// Number of bytes sent to the chip for programming.
+    pub total_bytes_programmed: u64,
+    /// Number of bytes erased, counting whole sectors, blocks or the whole chip.
+    pub total_bytes_erased: u64,
+}
+
+/// The identification and configuration of the chip, gathered for bug reports. See [W25q256jv::diagnostic_snapshot].
+/// Only available with the `diagnostics` feature.
+#[cfg(feature = "diagnostics")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Snapshot {
+    /// The manufacturer ID, memory type and capacity.
+    pub jedec_id: [u8; 3],
+    /// The factory-programmed 64-bit unique ID.
+    pub unique_id: [u8; 8],
+    /// The raw values of status registers 1 to 3.
+    pub status_registers: [u8; 3],
+    /// The chip is in 4-byte address mode (ADS).
+    pub four_byte_address_mode: bool,
+    /// The WP and HOLD pins act as data lines (QE).
+    pub quad_enabled: bool,
+    /// The block protection bits, including the complement protect bit CMP.
+    pub protection: BlockProtection,
+}
+
+/// A running checksum or hash that can be fed with data as it is read from the chip.
+/// See [W25q256jv::read_hashed].
+pub trait Digest {
+    /// Feeds the next chunk of data into the digest.
+    fn update(&mut self, data: &[u8]);
+}
+
+/// Describes a write made with [W25q256jv::write_token], so it can be verified later with [W25q256jv::verify_token].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WriteToken {
+    /// Address of the first written byte.
+    pub address: u32,
+    /// Number of written bytes.
+    pub len: u32,
+    /// [crc32::Crc32] checksum of the written data.
+    pub crc: u32,
+}
+
+/// A [DelayNs] implementation that doesn't wait at all. Used by the driver when no delay source is configured.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoDelay;
+
+impl DelayNs for NoDelay {
+    async fn delay_ns(&mut self, _ns: u32) {}
+}
 
 /// Low level driver for the W25q256jv flash memory chip.
-pub struct W25q256jv<SPI, HOLD, WP> {
+pub struct W25q256jv<SPI, HOLD, WP, DELAY = NoDelay> {
     spi: SPI,
     hold: HOLD,
     wp: WP,
+    /// Last state the hold pin was set to.
+    hold_state: PinState,
+    /// Last state the write protect pin was set to, if it was driven at all.
+    wp_state: Option<PinState>,
+    delay: DELAY,
+    inter_command_delay_ns: u32,
+    /// Also wait the inter-command delay between status polls. See [W25q256jv::with_shared_bus].
+    shared_bus: bool,
+    /// Set when a [suspend::EraseGuard] is dropped without resuming. The next operation resumes first.
+    resume_pending: bool,
+    /// Set while a program or erase issued by this driver hasn't been seen to complete,
+    /// e.g. because its future was dropped. See [Error::Busy].
+    in_operation: bool,
+    erase_retries: u8,
+    auto_erase_on_write: bool,
+    fast_write: bool,
+    max_write_len: usize,
+    timing: TimingProfile,
+    #[cfg(feature = "stats")]
+    stats: Stats,
+    /// Timestamps the durations of programs and erases logged with defmt.
+    #[cfg(feature = "defmt")]
+    clock: Option<fn() -> u32>,
+    chip_erase_sample_interval: u32,
+    /// Reused for framing commands, so hot paths don't build a new frame on the stack for every command.
+    /// Every command with an address or dummy bytes is framed here.
+    command: [u8; MAX_COMMAND_LEN],
 }
 
-impl<SPI, HOLD, WP> W25q256jv<SPI, HOLD, WP> {
+impl<SPI, HOLD, WP, DELAY> W25q256jv<SPI, HOLD, WP, DELAY> {
     /// Get the capacity of the flash chip in bytes.
     pub fn capacity() -> usize {
         CAPACITY as usize
@@ -38,33 +614,259 @@ where
     WP: OutputPin<Error = P>,
 {
     pub fn new(spi: SPI, hold: HOLD, wp: WP) -> Result<Self, Error<S, P>> {
-        let mut flash = Self { spi, hold, wp };
+        let mut flash = Self::new_no_wp_drive(spi, hold, wp)?;
+
+        flash.set_wp(PinState::High)?;
+
+        Ok(flash)
+    }
+
+    /// Creates the driver like [W25q256jv::new], but without driving the write protect pin.
+    ///
+    /// This is meant for boards with an external pull-up on the write protect pin, where the pin is
+    /// left to an external protection controller. The pin must be configured so it doesn't drive
+    /// the line, e.g. as open-drain output that is left high. Using [W25q256jv::set_wp] still drives the pin.
+    pub fn new_no_wp_drive(spi: SPI, hold: HOLD, wp: WP) -> Result<Self, Error<S, P>> {
+        let mut flash = Self {
+            spi,
+            hold,
+            wp,
+            hold_state: PinState::High,
+            wp_state: None,
+            delay: NoDelay,
+            inter_command_delay_ns: 0,
+            shared_bus: false,
+            resume_pending: false,
+            in_operation: false,
+            erase_retries: 0,
+            auto_erase_on_write: false,
+            fast_write: false,
+            max_write_len: usize::MAX,
+            timing: TimingProfile::DATASHEET,
+            #[cfg(feature = "stats")]
+            stats: Stats::default(),
+            #[cfg(feature = "defmt")]
+            clock: None,
+            chip_erase_sample_interval: BLOCK_64K_SIZE,
+            command: [0; MAX_COMMAND_LEN],
+        };
+
+        flash.set_hold(PinState::High)?;
+
+        Ok(flash)
+    }
+}
+
+impl<SPI, S: Debug, P: Debug, HOLD, WP> W25q256jv<SPI, HOLD, WP>
+where
+    SPI: embedded_hal_async::spi::SpiDevice<Error = S>,
+    HOLD: OutputPin<Error = P>,
+    WP: OutputPin<Error = P>,
+{
+    /// Creates the driver like [W25q256jv::new], then reads the JEDEC ID and returns [Error::UnexpectedId]
+    /// if the chip isn't a W25Q256JV.
+    ///
+    /// Use [W25q256jv::new] if no commands may be issued during construction, e.g. on a shared bus.
+    pub async fn new_with_verify(spi: SPI, hold: HOLD, wp: WP) -> Result<Self, Error<S, P>> {
+        let mut flash = Self::new(spi, hold, wp)?;
+
+        let id = flash.read_jedec_id().await?;
+        if !id.is_w25q256jv() {
+            return Err(Error::UnexpectedId { read: id.into() });
+        }
+
+        Ok(flash)
+    }
+}
+
+impl<SPI, S: Debug, P: Debug, HOLD, WP, DELAY> W25q256jv<SPI, HOLD, WP, DELAY>
+where
+    SPI: embedded_hal::spi::ErrorType<Error = S>,
+    HOLD: OutputPin<Error = P>,
+    WP: OutputPin<Error = P>,
+{
+    /// Configures an idle time between consecutive commands of operations that consist of multiple
+    /// commands, like writing multiple pages or erasing multiple sectors.
+    ///
+    /// Some (clone) chips need a short time with chip select deasserted before accepting the next command.
+    /// By default there is no delay.
+    ///
+    /// # Arguments
+    /// * `delay` - The delay source used to wait between commands.
+    /// * `inter_command_delay_ns` - The time to wait between commands in nanoseconds.
+    pub fn with_inter_command_delay<D: DelayNs>(
+        self,
+        delay: D,
+        inter_command_delay_ns: u32,
+    ) -> W25q256jv<SPI, HOLD, WP, D> {
+        W25q256jv {
+            spi: self.spi,
+            hold: self.hold,
+            wp: self.wp,
+            hold_state: self.hold_state,
+            wp_state: self.wp_state,
+            delay,
+            inter_command_delay_ns,
+            shared_bus: self.shared_bus,
+            resume_pending: self.resume_pending,
+            in_operation: self.in_operation,
+            erase_retries: self.erase_retries,
+            auto_erase_on_write: self.auto_erase_on_write,
+            fast_write: self.fast_write,
+            max_write_len: self.max_write_len,
+            timing: self.timing,
+            #[cfg(feature = "stats")]
+            stats: self.stats,
+            #[cfg(feature = "defmt")]
+            clock: self.clock,
+            chip_erase_sample_interval: self.chip_erase_sample_interval,
+            command: self.command,
+        }
+    }
 
-        flash.hold.set_high().map_err(Error::PinError)?;
-        flash.wp.set_high().map_err(Error::PinError)?;
+    /// Configures the driver for a bus shared with other devices, e.g. several flash chips sharing MOSI, MISO and SCK.
+    ///
+    /// Chip select handling and bus arbitration are up to the `SpiDevice` implementation. What the driver controls is
+    /// the time between its transactions and the hold pin. In this mode, the inter-command delay (see
+    /// [W25q256jv::with_inter_command_delay]) is also waited between the status polls while the chip is busy,
+    /// so polling a long erase doesn't occupy the bus back to back. The hold pin is deasserted, as an asserted hold
+    /// pin pauses the chip in the middle of a transaction while the other devices keep using the bus.
+    ///
+    /// # Arguments
+    /// * `delay` - The delay source used to wait between transactions.
+    /// * `inter_command_delay_ns` - The time to wait between transactions in nanoseconds.
+    pub fn with_shared_bus<D: DelayNs>(
+        self,
+        delay: D,
+        inter_command_delay_ns: u32,
+    ) -> Result<W25q256jv<SPI, HOLD, WP, D>, Error<S, P>> {
+        let mut flash = self.with_inter_command_delay(delay, inter_command_delay_ns);
+        flash.shared_bus = true;
+        flash.set_hold(PinState::High)?;
 
         Ok(flash)
     }
 
     /// Set the hold pin state.
     ///
-    /// The driver doesn't do anything with this pin. When using the chip, make sure the hold pin is not asserted.
-    /// By default, this means the pin needs to be high (true).
+    /// The driver only deasserts this pin, on creation, in [W25q256jv::with_shared_bus] and in [W25q256jv::release_bus].
+    /// When using the chip, make sure the hold pin is not asserted. By default, this means the pin needs to be high (true).
     ///
     /// This function sets the pin directly and can cause the chip to not work.
     pub fn set_hold(&mut self, value: PinState) -> Result<(), Error<S, P>> {
         self.hold.set_state(value).map_err(Error::PinError)?;
+        self.hold_state = value;
         Ok(())
     }
 
     /// Set the write protect pin state.
     ///
-    /// The driver doesn't do anything with this pin. When using the chip, make sure the hold pin is not asserted.
-    /// By default, this means the pin needs to be high (true).
+    /// Besides deasserting it on creation with [W25q256jv::new], the driver only drives this pin in
+    /// [W25q256jv::hardware_protect_status_register], [W25q256jv::hardware_unprotect_status_register] and
+    /// [W25q256jv::release_bus], the latter only if the pin was driven before. When using the chip, make sure
+    /// the write protect pin is not asserted. By default, this means the pin needs to be high (true).
     ///
     /// This function sets the pin directly and can cause the chip to not work.
     pub fn set_wp(&mut self, value: PinState) -> Result<(), Error<S, P>> {
         self.wp.set_state(value).map_err(Error::PinError)?;
+        self.wp_state = Some(value);
+        Ok(())
+    }
+
+    /// Get the states the hold and write protect pins were last set to by the driver, e.g. to restore them later.
+    ///
+    /// The write protect state is None if the pin was never driven, see [W25q256jv::new_no_wp_drive].
+    pub fn control_pin_states(&self) -> (PinState, Option<PinState>) {
+        (self.hold_state, self.wp_state)
+    }
+
+    /// Set how often a sector erase is retried when it fails verification with the `verify-erases` feature.
+    ///
+    /// A failing verification usually indicates a worn out sector, but a transient failure can clear on retry.
+    /// The default is 0, which returns [Error::ReadbackFail] on the first failure.
+    pub fn set_erase_retries(&mut self, retries: u8) {
+        self.erase_retries = retries;
+    }
+
+    /// Set whether writes through the `NorFlash` trait erase the sectors they target when needed.
+    ///
+    /// NOR flash can only clear bits, so writing to a region that isn't erased ANDs the data with the old contents.
+    /// With this option, a trait write that can't be programmed as is reads the affected sector, erases it and programs
+    /// it with the new data merged in (read-modify-write). This breaks with the NOR flash contract of the trait, so it is
+    /// disabled by default. It costs an extra erase cycle for each such write, and a SECTOR_SIZE buffer in the write future.
+    pub fn set_auto_erase_on_write(&mut self, enabled: bool) {
+        self.auto_erase_on_write = enabled;
+    }
+
+    /// Set whether the write-enable flag is trusted to be set after issuing Write Enable.
+    ///
+    /// By default, the flag is read back before every program and erase, and [Error::WriteEnableFail] is returned
+    /// if it isn't set. Skipping that status register read speeds up writing many pages in a row. If the command
+    /// is lost however, e.g. due to a glitch on the bus, the chip silently ignores the following program or erase.
+    /// Combine this with the `verify-writes` feature or a later check of the data where that matters.
+    pub fn set_fast_write(&mut self, enabled: bool) {
+        self.fast_write = enabled;
+    }
+
+    /// Set the maximum number of bytes a single [W25q256jv::write] may write. Longer writes return [Error::WriteTooLarge]
+    /// before anything is written, which catches length bugs in the caller early. The default is unlimited.
+    ///
+    /// The limit applies to the data passed by the caller, also with [W25q256jv::program_region] and the auto-erasing
+    /// `NorFlash::write`. The whole sectors these write back internally aren't subject to it.
+    pub fn set_max_write_len(&mut self, len: usize) {
+        self.max_write_len = len;
+    }
+
+    /// Get the statistics of the operations performed since the driver was created or the statistics were reset.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Reset the statistics of the operations performed.
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::default();
+    }
+
+    /// Set a clock to log the duration of every program and erase with defmt, e.g. to compare the actual
+    /// times of a chip against its timing profile on the RTT console.
+    ///
+    /// # Arguments
+    /// * `clock` - Returns the current time in ticks of any unit. Allowed to wrap around.
+    #[cfg(feature = "defmt")]
+    pub fn set_clock(&mut self, clock: fn() -> u32) {
+        self.clock = Some(clock);
+    }
+
+    /// Set the typical operation times used to pace the busy polling during operations.
+    /// See [W25q256jv::load_timing_profile_from_sfdp] to derive them from the chip itself.
+    pub fn set_timing_profile(&mut self, timing: TimingProfile) {
+        self.timing = timing;
+    }
+
+    /// Get the typical operation times used to pace the busy polling during operations.
+    pub fn timing_profile(&self) -> TimingProfile {
+        self.timing
+    }
+
+    /// Set how densely [W25q256jv::is_chip_erased] samples the chip, which the `verify-erases` feature also uses after a chip erase.
+    ///
+    /// Reading back all of the chip takes very long, so instead only the first and last page of every
+    /// `interval` bytes are checked. The default is BLOCK_64K_SIZE. An interval of PAGE_SIZE checks every page.
+    ///
+    /// # Arguments
+    /// * `interval` - Distance in bytes between samples. Must be a non-zero multiple of PAGE_SIZE.
+    pub fn set_chip_erase_sample_interval(&mut self, interval: u32) -> Result<(), Error<S, P>> {
+        if interval == 0 || !interval.is_multiple_of(PAGE_SIZE) {
+            return Err(Error::NotAligned);
+        }
+
+        if interval > CAPACITY {
+            return Err(Error::OutOfBounds);
+        }
+
+        self.chip_erase_sample_interval = interval;
         Ok(())
     }
 
@@ -74,7 +876,7 @@ where
     }
 }
 
-impl<SPI, S: Debug, P: Debug, HOLD, WP> ErrorType for W25q256jv<SPI, HOLD, WP>
+impl<SPI, S: Debug, P: Debug, HOLD, WP, DELAY> ErrorType for W25q256jv<SPI, HOLD, WP, DELAY>
 where
     HOLD: OutputPin<Error = P>,
     P: Debug,
@@ -97,6 +899,55 @@ pub enum Error<S: Debug, P: Debug> {
     OutOfBounds,
     WriteEnableFail,
     ReadbackFail,
+    /// All bits of a unary counter are cleared already, see [W25q256jv::increment_unary_counter].
+    CounterExhausted,
+    /// A caller-provided buffer is too short for the data, e.g. the scratch buffer of
+    /// [W25q256jv::write_and_verify_delta] or the record buffer of [W25q256jv::read_record].
+    BufferTooSmall,
+    /// The JEDEC ID read from the chip isn't the W25Q256JV's, e.g. on creation with [W25q256jv::new_with_verify].
+    UnexpectedId {
+        /// The JEDEC ID that was read.
+        read: [u8; 3],
+    },
+    /// The SFDP table doesn't start with the SFDP signature or lacks the basic flash parameter table.
+    InvalidSfdp,
+    /// The chip didn't switch to the requested address mode, as read back from status register 3.
+    AddressModeFail,
+    /// A program or erase was started while a previous one, whose future was dropped, is still running.
+    Busy,
+    /// The operation was cancelled through its cancellation flag.
+    Cancelled,
+    /// The write is longer than allowed with [W25q256jv::set_max_write_len].
+    WriteTooLarge,
+    /// Data that was expected to be a string isn't valid UTF-8.
+    InvalidUtf8,
+}
+
+impl<S: Debug, P: Debug> Error<S, P> {
+    /// Returns true if the error is an [Error::OutOfBounds], which maps to `NorFlashErrorKind::OutOfBounds`.
+    pub fn is_out_of_bounds(&self) -> bool {
+        matches!(self, Error::OutOfBounds)
+    }
+
+    /// Returns true if the error is an [Error::NotAligned], which maps to `NorFlashErrorKind::NotAligned`.
+    pub fn is_not_aligned(&self) -> bool {
+        matches!(self, Error::NotAligned)
+    }
+
+    /// Returns true if the error is an [Error::SpiError].
+    pub fn is_spi_error(&self) -> bool {
+        matches!(self, Error::SpiError(_))
+    }
+
+    /// Returns true if the error is an [Error::PinError].
+    pub fn is_pin_error(&self) -> bool {
+        matches!(self, Error::PinError(_))
+    }
+
+    /// Returns true if the error is an [Error::ReadbackFail].
+    pub fn is_readback_fail(&self) -> bool {
+        matches!(self, Error::ReadbackFail)
+    }
 }
 
 impl<S: Debug, P: Debug> NorFlashError for Error<S, P> {
@@ -109,33 +960,561 @@ impl<S: Debug, P: Debug> NorFlashError for Error<S, P> {
     }
 }
 
+/// Checks that a read or write of `len` bytes at `address` lies within the chip.
+/// Mirrors embedded-storage's `check_read` and `check_write` helpers. As READ_SIZE and WRITE_SIZE are 1,
+/// there are no alignment requirements.
+fn check_slice<S: Debug, P: Debug>(address: u32, len: usize) -> Result<(), Error<S, P>> {
+    if len > CAPACITY as usize || address as usize > CAPACITY as usize - len {
+        return Err(Error::OutOfBounds);
+    }
+
+    Ok(())
+}
+
+/// Checks that an erase of the range [from..to] lies within the chip and is aligned to the erase size.
+/// Mirrors embedded-storage's `check_erase` helper, including the order in which errors are reported.
+fn check_erase<S: Debug, P: Debug>(from: u32, to: u32, erase_size: u32) -> Result<(), Error<S, P>> {
+    if from > to || to > CAPACITY {
+        return Err(Error::OutOfBounds);
+    }
+
+    if !from.is_multiple_of(erase_size) || !to.is_multiple_of(erase_size) {
+        return Err(Error::NotAligned);
+    }
+
+    Ok(())
+}
+
 /// Easily readable representation of the command bytes used by the flash chip.
 /// 4-byte addressing mode
 #[repr(u8)]
 enum Command {
     WriteEnable = 0x06,
-    // WriteDisable = 0x04,
-    // ReadUniqueId = 0x4B,
+    WriteDisable = 0x04,
+    WriteEnableForVolatileStatusRegister = 0x50,
+    ReadUniqueId = 0x4B,
     ReadDataWith4ByteAddress = 0x13,
+    FastReadWith4ByteAddress = 0x0C,
     PageProgramWith4ByteAddress = 0x12,
     SectorErase4KBWith4ByteAddress = 0x21,
-    BlockErase32KB = 0x52, // can be used in both 3-byte and 4-byte addressing modes
+    // BlockErase32KB = 0x52, // takes a 3-byte address unless the chip is in 4-byte address mode
+    BlockErase32KBWith4ByteAddress = 0x5C,
     BlockErase64KBWith4ByteAddress = 0xDC,
     ChipErase = 0xC7, // alternatively 0x60 can be used
     ReadStatusRegister1 = 0x05,
+    ReadJedecId = 0x9F,
+    ReadSfdp = 0x5A,
+    ReadStatusRegister2 = 0x35,
+    ReadStatusRegister3 = 0x15,
+    WriteStatusRegister1 = 0x01,
+    WriteStatusRegister2 = 0x31,
+    WriteStatusRegister3 = 0x11,
     EnableReset = 0x66,
     ResetDevice = 0x99,
     Enter4ByteAddressMode = 0xB7,
     Exit4ByteAddressMode = 0xE9,
+    PowerDown = 0xB9,
+    ReleasePowerDown = 0xAB,
+    ReadSecurityRegister = 0x48,
+    ProgramSecurityRegister = 0x42,
+    EraseSecurityRegister = 0x44,
+    EraseProgramSuspend = 0x75,
+    EraseProgramResume = 0x7A,
 }
 
-fn command_and_address(command: u8, address: u32) -> [u8; 5] {
-    let addr_bytes = address.to_be_bytes();
-    [
-        command,
-        addr_bytes[0],
-        addr_bytes[1],
-        addr_bytes[2],
-        addr_bytes[3],
-    ]
+/// Marks a copy written by [W25q256jv::shadow_commit] as complete.
+const COMMIT_MARKER: [u8; 4] = *b"CMIT";
+
+/// Length of the longest command frame: the command byte, a 4-byte address and a dummy byte.
+const MAX_COMMAND_LEN: usize = 6;
+
+/// Frames a command followed by a 4-byte address into the provided buffer.
+/// Returns the part of the buffer that holds the frame.
+fn command_and_address(buf: &mut [u8; MAX_COMMAND_LEN], command: u8, address: u32) -> &[u8] {
+    buf[0] = command;
+    buf[1..5].copy_from_slice(&address.to_be_bytes());
+    &buf[..5]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    #[test]
+    fn command_and_address_frames_4_byte_address() {
+        let mut buf = [0xAA; MAX_COMMAND_LEN];
+
+        assert_eq!(
+            command_and_address(&mut buf, 0x13, 0x0123_4567),
+            [0x13, 0x01, 0x23, 0x45, 0x67]
+        );
+        assert_eq!(buf[5], 0xAA, "the byte after the address is left alone");
+    }
+
+    #[test]
+    fn density_from_capacity_byte() {
+        assert_eq!(Density::try_from(0x17), Ok(Density::Mbit64));
+        assert_eq!(Density::try_from(0x18), Ok(Density::Mbit128));
+        assert_eq!(Density::try_from(0x19), Ok(Density::Mbit256));
+    }
+
+    #[test]
+    fn density_round_trip() {
+        for density in [Density::Mbit64, Density::Mbit128, Density::Mbit256] {
+            assert_eq!(Density::try_from(u8::from(density)), Ok(density));
+        }
+    }
+
+    #[test]
+    fn density_unknown_byte() {
+        assert_eq!(Density::try_from(0x16), Err(0x16));
+        assert_eq!(Density::try_from(0x1A), Err(0x1A));
+        assert_eq!(Density::try_from(0xFF), Err(0xFF));
+    }
+
+    #[test]
+    fn jedec_id_density() {
+        assert_eq!(JedecId::from(JEDEC_ID).density(), Ok(Density::Mbit256));
+        assert_eq!(
+            JedecId::from([0xEF, 0x40, 0x18]).density(),
+            Ok(Density::Mbit128)
+        );
+        assert_eq!(JedecId::from([0xEF, 0x40, 0x00]).density(), Err(0x00));
+
+        assert!(JedecId::from(JEDEC_ID).is_w25q256jv());
+        assert!(!JedecId::from([0xEF, 0x40, 0x18]).is_w25q256jv());
+        assert!(!JedecId::from([0xC2, 0x40, 0x19]).is_w25q256jv());
+    }
+
+    /// A blocking NorFlash with the same constants as the driver, for comparing against embedded-storage's helpers.
+    struct Geometry;
+
+    impl embedded_storage::nor_flash::ErrorType for Geometry {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl embedded_storage::nor_flash::ReadNorFlash for Geometry {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, _: u32, _: &mut [u8]) -> Result<(), Self::Error> {
+            unimplemented!()
+        }
+
+        fn capacity(&self) -> usize {
+            CAPACITY as usize
+        }
+    }
+
+    impl embedded_storage::nor_flash::NorFlash for Geometry {
+        const WRITE_SIZE: usize = 1;
+        const ERASE_SIZE: usize = SECTOR_SIZE as usize;
+
+        fn erase(&mut self, _: u32, _: u32) -> Result<(), Self::Error> {
+            unimplemented!()
+        }
+
+        fn write(&mut self, _: u32, _: &[u8]) -> Result<(), Self::Error> {
+            unimplemented!()
+        }
+    }
+
+    fn kind(result: Result<(), Error<(), ()>>) -> Result<(), NorFlashErrorKind> {
+        result.map_err(|error| error.kind())
+    }
+
+    #[test]
+    fn check_slice_matches_embedded_storage() {
+        use embedded_storage::nor_flash::{check_read, check_write};
+
+        let addresses = [
+            0,
+            1,
+            PAGE_SIZE,
+            CAPACITY / 2,
+            CAPACITY - 1,
+            CAPACITY,
+            CAPACITY + 1,
+            u32::MAX,
+        ];
+        let lens = [
+            0,
+            1,
+            2,
+            PAGE_SIZE as usize,
+            CAPACITY as usize - 1,
+            CAPACITY as usize,
+            CAPACITY as usize + 1,
+        ];
+
+        for address in addresses {
+            for len in lens {
+                let expected = check_read(&Geometry, address, len);
+                assert_eq!(expected, check_write(&Geometry, address, len));
+                assert_eq!(
+                    kind(check_slice(address, len)),
+                    expected,
+                    "{address:#x} + {len}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn check_erase_matches_embedded_storage() {
+        let addresses = [
+            0,
+            1,
+            SECTOR_SIZE - 1,
+            SECTOR_SIZE,
+            SECTOR_SIZE + 1,
+            CAPACITY - SECTOR_SIZE,
+            CAPACITY - 1,
+            CAPACITY,
+            CAPACITY + 1,
+            CAPACITY + SECTOR_SIZE,
+            u32::MAX,
+        ];
+
+        for from in addresses {
+            for to in addresses {
+                assert_eq!(
+                    kind(check_erase(from, to, SECTOR_SIZE)),
+                    embedded_storage::nor_flash::check_erase(&Geometry, from, to),
+                    "{from:#x}..{to:#x}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn range_within_a_sector() {
+        assert!(within_single_sector(SECTOR_SIZE + 10, 100));
+        assert!(within_single_sector(SECTOR_SIZE, 0));
+        assert!(within_single_sector(CAPACITY - 1, 1));
+    }
+
+    #[test]
+    fn range_exactly_filling_a_sector() {
+        assert!(within_single_sector(0, SECTOR_SIZE));
+        assert!(within_single_sector(CAPACITY - SECTOR_SIZE, SECTOR_SIZE));
+        assert!(!within_single_sector(0, SECTOR_SIZE + 1));
+    }
+
+    #[test]
+    fn range_crossing_a_sector_boundary() {
+        assert!(!within_single_sector(SECTOR_SIZE - 1, 2));
+        assert!(!within_single_sector(SECTOR_SIZE / 2, SECTOR_SIZE));
+        assert!(!within_single_sector(u32::MAX, u32::MAX));
+    }
+
+    #[test]
+    fn block_64k_index_at_boundaries() {
+        assert_eq!(block_64k_index(0), 0);
+        assert_eq!(block_64k_index(BLOCK_64K_SIZE - 1), 0);
+        assert_eq!(block_64k_index(BLOCK_64K_SIZE), 1);
+        assert_eq!(block_64k_index(CAPACITY - 1), N_BLOCKS_64K - 1);
+    }
+
+    #[test]
+    fn block_64k_base_round_trip() {
+        assert_eq!(block_64k_base(0), 0);
+        assert_eq!(block_64k_base(1), BLOCK_64K_SIZE);
+        assert_eq!(block_64k_base(N_BLOCKS_64K - 1), CAPACITY - BLOCK_64K_SIZE);
+
+        for address in [0, 12345, BLOCK_64K_SIZE + 1, CAPACITY - 1] {
+            let base = block_64k_base(block_64k_index(address));
+            assert!(base <= address && address - base < BLOCK_64K_SIZE);
+        }
+    }
+
+    #[test]
+    fn new_drives_hold_and_wp_high() {
+        let sim = mock::Sim::default();
+        let flash = W25q256jv::new(
+            mock::Spi(sim.clone()),
+            mock::Pin::hold(&sim),
+            mock::Pin::wp(&sim),
+        )
+        .unwrap();
+
+        assert_eq!(
+            sim.borrow().log,
+            [
+                mock::Event::Hold(PinState::High),
+                mock::Event::Wp(PinState::High)
+            ]
+        );
+        assert_eq!(
+            flash.control_pin_states(),
+            (PinState::High, Some(PinState::High))
+        );
+    }
+
+    #[test]
+    fn new_no_wp_drive_leaves_wp_alone() {
+        let sim = mock::Sim::default();
+        let flash = W25q256jv::new_no_wp_drive(
+            mock::Spi(sim.clone()),
+            mock::Pin::hold(&sim),
+            mock::Pin::wp(&sim),
+        )
+        .unwrap();
+
+        assert_eq!(sim.borrow().log, [mock::Event::Hold(PinState::High)]);
+        assert_eq!(flash.control_pin_states(), (PinState::High, None));
+    }
+
+    #[test]
+    fn check_slice_up_to_the_last_byte() {
+        for n in [0, 1, 2, PAGE_SIZE as usize, CAPACITY as usize] {
+            assert!(check_slice::<(), ()>(CAPACITY - n as u32, n).is_ok());
+        }
+        assert!(check_slice::<(), ()>(CAPACITY, 0).is_ok());
+    }
+
+    #[test]
+    fn check_slice_past_the_last_byte() {
+        for n in [0, 1, 2, PAGE_SIZE as usize, CAPACITY as usize - 1] {
+            assert!(matches!(
+                check_slice::<(), ()>(CAPACITY - n as u32, n + 1),
+                Err(Error::OutOfBounds)
+            ));
+        }
+        assert!(matches!(
+            check_slice::<(), ()>(CAPACITY, 1),
+            Err(Error::OutOfBounds)
+        ));
+        assert!(matches!(
+            check_slice::<(), ()>(0, CAPACITY as usize + 1),
+            Err(Error::OutOfBounds)
+        ));
+    }
+
+    #[test]
+    fn timing_profile_falls_back_to_datasheet() {
+        assert_eq!(
+            TimingProfile::from_basic_parameter_table(&[]),
+            TimingProfile::DATASHEET
+        );
+        // Too short for the timing DWORDs
+        assert_eq!(
+            TimingProfile::from_basic_parameter_table(&[0; 36]),
+            TimingProfile::DATASHEET
+        );
+    }
+
+    #[test]
+    fn supply_currents_are_sane() {
+        const {
+            assert!(DEEP_POWER_DOWN_CURRENT_UA < STANDBY_CURRENT_UA);
+            assert!(STANDBY_CURRENT_UA < PROGRAM_CURRENT_MA * 1000);
+            assert!(PROGRAM_CURRENT_MA > 0 && PROGRAM_CURRENT_MA <= 50);
+            assert!(ERASE_CURRENT_MA > 0 && ERASE_CURRENT_MA <= 50);
+        }
+    }
+
+    #[test]
+    fn error_predicates() {
+        type E = Error<&'static str, ()>;
+
+        assert!(E::OutOfBounds.is_out_of_bounds());
+        assert!(E::NotAligned.is_not_aligned());
+        assert!(E::SpiError("bus").is_spi_error());
+        assert!(E::PinError(()).is_pin_error());
+        assert!(E::ReadbackFail.is_readback_fail());
+
+        for error in [E::Busy, E::WriteEnableFail, E::BufferTooSmall] {
+            assert!(!error.is_out_of_bounds());
+            assert!(!error.is_not_aligned());
+            assert!(!error.is_spi_error());
+            assert!(!error.is_pin_error());
+            assert!(!error.is_readback_fail());
+        }
+    }
+
+    #[test]
+    fn error_kinds() {
+        type E = Error<(), ()>;
+
+        assert_eq!(E::OutOfBounds.kind(), NorFlashErrorKind::OutOfBounds);
+        assert_eq!(E::NotAligned.kind(), NorFlashErrorKind::NotAligned);
+        assert_eq!(E::SpiError(()).kind(), NorFlashErrorKind::Other);
+        assert_eq!(E::ReadbackFail.kind(), NorFlashErrorKind::Other);
+    }
+
+    #[test]
+    fn chip_erase_sample_interval_must_be_whole_pages_within_the_chip() {
+        let (_, mut flash) = mock::flash();
+
+        assert!(matches!(
+            flash.set_chip_erase_sample_interval(0),
+            Err(Error::NotAligned)
+        ));
+        assert!(matches!(
+            flash.set_chip_erase_sample_interval(PAGE_SIZE + 1),
+            Err(Error::NotAligned)
+        ));
+        assert!(matches!(
+            flash.set_chip_erase_sample_interval(2 * CAPACITY),
+            Err(Error::OutOfBounds)
+        ));
+        assert!(matches!(
+            flash.set_chip_erase_sample_interval(CAPACITY + PAGE_SIZE),
+            Err(Error::OutOfBounds)
+        ));
+        flash.set_chip_erase_sample_interval(CAPACITY).unwrap();
+    }
+
+    #[test]
+    fn protected_range_follows_the_datasheet_table() {
+        let range = |sr1, sr2| BlockProtection::from_status_registers(sr1, sr2).protected_range();
+
+        assert!(range(0x00, 0x00).is_empty());
+        // BP=0001, the upper 64KB
+        assert_eq!(range(0x04, 0x00), CAPACITY - BLOCK_64K_SIZE..CAPACITY);
+        // BP=0011 with TB, the lower 256KB
+        assert_eq!(range(0x4C, 0x00), 0..4 * BLOCK_64K_SIZE);
+        // BP=1010 and above protect everything
+        assert_eq!(range(0x28, 0x00), 0..CAPACITY);
+        assert_eq!(range(0x3C, 0x00), 0..CAPACITY);
+
+        // CMP inverts the region
+        assert_eq!(range(0x00, 0x40), 0..CAPACITY);
+        assert_eq!(range(0x04, 0x40), 0..CAPACITY - BLOCK_64K_SIZE);
+        assert_eq!(range(0x4C, 0x40), 4 * BLOCK_64K_SIZE..CAPACITY);
+        assert!(range(0x3C, 0x40).is_empty());
+    }
+
+    #[test]
+    fn largest_aligned_block_by_alignment() {
+        assert_eq!(
+            largest_aligned_block(0),
+            (BLOCK_64K_SIZE, EraseUnit::Block64K)
+        );
+        assert_eq!(
+            largest_aligned_block(5 * BLOCK_64K_SIZE),
+            (BLOCK_64K_SIZE, EraseUnit::Block64K)
+        );
+        assert_eq!(
+            largest_aligned_block(5 * BLOCK_64K_SIZE + BLOCK_32K_SIZE),
+            (BLOCK_32K_SIZE, EraseUnit::Block32K)
+        );
+        assert_eq!(
+            largest_aligned_block(BLOCK_32K_SIZE + SECTOR_SIZE),
+            (SECTOR_SIZE, EraseUnit::Sector)
+        );
+        // Not erasable, but still within a sector
+        assert_eq!(
+            largest_aligned_block(SECTOR_SIZE + 1),
+            (SECTOR_SIZE, EraseUnit::Sector)
+        );
+    }
+
+    #[test]
+    fn control_pin_states_track_set_hold_and_set_wp() {
+        let (sim, mut flash) = mock::flash();
+
+        flash.set_hold(PinState::Low).unwrap();
+        assert_eq!(
+            flash.control_pin_states(),
+            (PinState::Low, Some(PinState::High))
+        );
+
+        flash.set_wp(PinState::Low).unwrap();
+        flash.set_hold(PinState::High).unwrap();
+        assert_eq!(
+            flash.control_pin_states(),
+            (PinState::High, Some(PinState::Low))
+        );
+        assert_eq!(
+            sim.borrow().log,
+            [
+                mock::Event::Hold(PinState::Low),
+                mock::Event::Wp(PinState::Low),
+                mock::Event::Hold(PinState::High)
+            ]
+        );
+    }
+
+    #[test]
+    fn sector_count_of_a_single_sector() {
+        assert_eq!(sector_count(SECTOR_SIZE, 2 * SECTOR_SIZE), 1);
+        assert_eq!(sector_count(SECTOR_SIZE + 10, SECTOR_SIZE + 20), 1);
+        assert_eq!(sector_count(SECTOR_SIZE, SECTOR_SIZE), 0);
+        assert_eq!(sector_count(2 * SECTOR_SIZE, SECTOR_SIZE), 0);
+    }
+
+    #[test]
+    fn sector_count_of_several_sectors() {
+        assert_eq!(sector_count(0, 5 * SECTOR_SIZE), 5);
+        // Partially covered sectors at both ends
+        assert_eq!(sector_count(SECTOR_SIZE - 1, 3 * SECTOR_SIZE + 1), 4);
+        assert_eq!(sector_count(0, CAPACITY), N_SECTORS);
+    }
+
+    #[test]
+    fn new_with_verify_accepts_a_w25q256jv() {
+        let sim = mock::Sim::default();
+
+        embassy_futures::block_on(W25q256jv::new_with_verify(
+            mock::Spi(sim.clone()),
+            mock::Pin::hold(&sim),
+            mock::Pin::wp(&sim),
+        ))
+        .unwrap();
+        assert_eq!(sim.borrow().commands(0x9F).len(), 1);
+    }
+
+    #[test]
+    fn new_with_verify_rejects_another_chip() {
+        let sim = mock::Sim::default();
+        sim.borrow_mut().jedec_id = [0xEF, 0x40, 0x18];
+
+        let result = embassy_futures::block_on(W25q256jv::new_with_verify(
+            mock::Spi(sim.clone()),
+            mock::Pin::hold(&sim),
+            mock::Pin::wp(&sim),
+        ));
+        assert!(matches!(
+            result,
+            Err(Error::UnexpectedId {
+                read: [0xEF, 0x40, 0x18]
+            })
+        ));
+    }
+
+    #[test]
+    fn plan_write_within_one_sector() {
+        let plan = plan_write(SECTOR_SIZE + 0x80, 0x200);
+
+        assert_eq!(
+            plan,
+            WritePlan {
+                address: SECTOR_SIZE + 0x80,
+                len: 0x200,
+                first_sector: 1,
+                sector_count: 1,
+                first_page: 16,
+                page_count: 3,
+            }
+        );
+        assert!(plan.programs().eq([
+            (SECTOR_SIZE + 0x80, 0x80),
+            (SECTOR_SIZE + 0x100, 0x100),
+            (SECTOR_SIZE + 0x200, 0x80)
+        ]));
+    }
+
+    #[test]
+    fn plan_write_spanning_three_sectors() {
+        let plan = plan_write(2 * SECTOR_SIZE - 10, SECTOR_SIZE + 20);
+
+        assert_eq!(plan.first_sector, 1);
+        assert_eq!(plan.sector_count, 3);
+        assert_eq!(plan.page_count, 18);
+        assert_eq!(plan.programs().next(), Some((2 * SECTOR_SIZE - 10, 10)));
+        assert_eq!(plan.programs().last(), Some((3 * SECTOR_SIZE, 10)));
+        assert_eq!(plan.programs().map(|(_, len)| len).sum::<u32>(), plan.len);
+    }
 }
@@ -0,0 +1,303 @@
+use super::*;
+use core::fmt::Debug;
+use embedded_hal::digital::OutputPin;
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::spi::SpiDevice;
+
+/// Append-only log of variable length entries in a region of the chip, e.g. for event logs or journals.
+///
+/// Entries are stored as records, see [W25q256jv::write_record]. An erased length prefix marks the end of the log.
+/// Sectors are erased just before the log grows into them, and the sector the head points to is always erased,
+/// so the end of the log can be found again after a restart with [LogStore::open].
+pub struct LogStore<'a, SPI, HOLD, WP, DELAY = NoDelay> {
+    flash: &'a mut W25q256jv<SPI, HOLD, WP, DELAY>,
+    start: u32,
+    end: u32,
+    /// Address where the next entry will be appended.
+    head: u32,
+}
+
+/// Position of the next entry to replay from a [LogStore]. See [LogStore::entries].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Entries {
+    address: u32,
+}
+
+impl<'a, SPI, S: Debug, P: Debug, HOLD, WP, DELAY> LogStore<'a, SPI, HOLD, WP, DELAY>
+where
+    SPI: SpiDevice<Error = S>,
+    HOLD: OutputPin<Error = P>,
+    WP: OutputPin<Error = P>,
+    DELAY: DelayNs,
+    S: Debug,
+    P: Debug,
+{
+    /// Creates an empty log in the provided region, erasing its first sector.
+    ///
+    /// # Arguments
+    /// * `flash` - The flash chip to store the log on.
+    /// * `start` - Address of the first byte of the region. Must be a multiple of SECTOR_SIZE.
+    /// * `end` - Address of the first byte after the region. Must be a multiple of SECTOR_SIZE.
+    pub async fn format(
+        flash: &'a mut W25q256jv<SPI, HOLD, WP, DELAY>,
+        start: u32,
+        end: u32,
+    ) -> Result<Self, Error<S, P>> {
+        check_region(start, end)?;
+
+        flash.erase_sector(start / SECTOR_SIZE).await?;
+
+        Ok(Self {
+            flash,
+            start,
+            end,
+            head: start,
+        })
+    }
+
+    /// Opens a log previously created with [LogStore::format] in the provided region,
+    /// scanning it for the end of the log.
+    ///
+    /// # Arguments
+    /// * `flash` - The flash chip the log is stored on.
+    /// * `start` - Address of the first byte of the region. Must be a multiple of SECTOR_SIZE.
+    /// * `end` - Address of the first byte after the region. Must be a multiple of SECTOR_SIZE.
+    pub async fn open(
+        flash: &'a mut W25q256jv<SPI, HOLD, WP, DELAY>,
+        start: u32,
+        end: u32,
+    ) -> Result<Self, Error<S, P>> {
+        check_region(start, end)?;
+
+        let mut head = start;
+        while head + RECORD_HEADER_LEN <= end {
+            let mut header = [0; RECORD_HEADER_LEN as usize];
+            flash.read(head, &mut header).await?;
+
+            let len = u16::from_le_bytes(header);
+            if len == u16::MAX {
+                break;
+            }
+
+            head += RECORD_HEADER_LEN + len as u32;
+        }
+
+        Ok(Self {
+            flash,
+            start,
+            end,
+            head: core::cmp::min(head, end),
+        })
+    }
+
+    /// Returns the address where the next entry will be appended.
+    pub fn head(&self) -> u32 {
+        self.head
+    }
+
+    /// Appends an entry to the log, erasing the sectors it grows into.
+    ///
+    /// Returns the address of the entry, or [Error::OutOfBounds] if it doesn't fit into the rest of the region.
+    pub async fn append(&mut self, data: &[u8]) -> Result<u32, Error<S, P>> {
+        if data.len() >= u16::MAX as usize {
+            return Err(Error::OutOfBounds);
+        }
+
+        let address = self.head;
+        let next = address + RECORD_HEADER_LEN + data.len() as u32;
+        if next > self.end {
+            return Err(Error::OutOfBounds);
+        }
+
+        // The sector containing the head is erased already. Also erase the sectors the header at the new head
+        // lands on, which may start in one sector and end in the next, so the end of the log is found again by open.
+        let first_sector = address / SECTOR_SIZE + 1;
+        let last_sector = core::cmp::min(
+            (next + RECORD_HEADER_LEN - 1) / SECTOR_SIZE,
+            self.end / SECTOR_SIZE - 1,
+        );
+        for sector in first_sector..=last_sector {
+            self.flash.erase_sector(sector).await?;
+        }
+
+        self.head = self.flash.write_record(address, data).await?;
+
+        Ok(address)
+    }
+
+    /// Returns a cursor to replay the entries from the start of the log.
+    pub fn entries(&self) -> Entries {
+        Entries {
+            address: self.start,
+        }
+    }
+
+    /// Returns a cursor to replay the entries from the entry at the provided address, e.g. as returned by [LogStore::append].
+    pub fn entries_from(&self, address: u32) -> Entries {
+        Entries { address }
+    }
+}
+
+impl Entries {
+    /// Reads the next entry of the log into the provided buffer.
+    ///
+    /// Returns the length of the entry, or None at the end of the log.
+    pub async fn next<SPI, S: Debug, P: Debug, HOLD, WP, DELAY>(
+        &mut self,
+        store: &mut LogStore<'_, SPI, HOLD, WP, DELAY>,
+        buf: &mut [u8],
+    ) -> Result<Option<usize>, Error<S, P>>
+    where
+        SPI: SpiDevice<Error = S>,
+        HOLD: OutputPin<Error = P>,
+        WP: OutputPin<Error = P>,
+        DELAY: DelayNs,
+    {
+        if self.address >= store.head {
+            return Ok(None);
+        }
+
+        let len = store.flash.read_record(self.address, buf).await?;
+        if let Some(len) = len {
+            self.address += RECORD_HEADER_LEN + len as u32;
+        }
+
+        Ok(len)
+    }
+}
+
+/// Checks that a region is a non-empty range of whole sectors on the chip.
+fn check_region<S: Debug, P: Debug>(start: u32, end: u32) -> Result<(), Error<S, P>> {
+    check_erase(start, end, SECTOR_SIZE)?;
+
+    if start == end {
+        return Err(Error::OutOfBounds);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::mock;
+    use embassy_futures::block_on;
+    use std::vec::Vec;
+
+    const START: u32 = 8 * SECTOR_SIZE;
+    const END: u32 = 11 * SECTOR_SIZE;
+
+    fn entry(i: usize) -> Vec<u8> {
+        std::vec![i as u8; 500 + i * 37]
+    }
+
+    fn replay<SPI, S: Debug, P: Debug, HOLD, WP, DELAY>(
+        store: &mut LogStore<'_, SPI, HOLD, WP, DELAY>,
+        mut entries: Entries,
+    ) -> Vec<Vec<u8>>
+    where
+        SPI: SpiDevice<Error = S>,
+        HOLD: OutputPin<Error = P>,
+        WP: OutputPin<Error = P>,
+        DELAY: DelayNs,
+    {
+        let mut replayed = Vec::new();
+        let mut buf = [0; 1024];
+        while let Some(len) = block_on(entries.next(store, &mut buf)).unwrap() {
+            replayed.push(buf[..len].to_vec());
+        }
+
+        replayed
+    }
+
+    #[test]
+    fn append_and_replay_across_a_sector_boundary() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut()
+            .set_memory(START, &[0x00; (END - START) as usize]);
+
+        let mut store = block_on(LogStore::format(&mut flash, START, END)).unwrap();
+        let mut addresses = Vec::new();
+        for i in 0..10 {
+            addresses.push(block_on(store.append(&entry(i))).unwrap());
+        }
+        assert!(addresses[0] < START + SECTOR_SIZE);
+        assert!(
+            addresses[9] > START + SECTOR_SIZE,
+            "the log crossed into the second sector"
+        );
+
+        let expected: Vec<Vec<u8>> = (0..10).map(entry).collect();
+        let entries = store.entries();
+        assert_eq!(replay(&mut store, entries), expected);
+        let entries = store.entries_from(addresses[7]);
+        assert_eq!(replay(&mut store, entries), expected[7..]);
+    }
+
+    #[test]
+    fn open_finds_the_end_of_the_log() {
+        let (_, mut flash) = mock::flash();
+
+        let head = {
+            let mut store = block_on(LogStore::format(&mut flash, START, END)).unwrap();
+            for i in 0..10 {
+                block_on(store.append(&entry(i))).unwrap();
+            }
+            store.head()
+        };
+
+        let mut store = block_on(LogStore::open(&mut flash, START, END)).unwrap();
+        assert_eq!(store.head(), head);
+        block_on(store.append(b"after restart")).unwrap();
+
+        let entries = store.entries();
+        let replayed = replay(&mut store, entries);
+        assert_eq!(replayed.len(), 11);
+        assert_eq!(replayed[10], b"after restart");
+    }
+
+    #[test]
+    fn append_rejects_an_entry_past_the_end() {
+        let (_, mut flash) = mock::flash();
+        let mut store = block_on(LogStore::format(&mut flash, START, START + SECTOR_SIZE)).unwrap();
+
+        assert!(matches!(
+            block_on(store.append(&[0; SECTOR_SIZE as usize])),
+            Err(Error::OutOfBounds)
+        ));
+        assert_eq!(store.head(), START);
+    }
+
+    #[test]
+    fn open_stops_at_a_head_one_byte_before_a_sector_boundary() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut()
+            .set_memory(START, &[0x00; (END - START) as usize]);
+
+        // The entries end one byte before the second sector, so the next header spans both sectors
+        let lens = [1022, 1022, 1022, 1021];
+        let head = {
+            let mut store = block_on(LogStore::format(&mut flash, START, END)).unwrap();
+            for (i, &len) in lens.iter().enumerate() {
+                block_on(store.append(&std::vec![i as u8; len])).unwrap();
+            }
+            store.head()
+        };
+        assert_eq!(head, START + SECTOR_SIZE - 1);
+
+        let mut store = block_on(LogStore::open(&mut flash, START, END)).unwrap();
+        assert_eq!(store.head(), head);
+
+        let entries = store.entries();
+        let replayed = replay(&mut store, entries);
+        let expected: Vec<Vec<u8>> = lens
+            .iter()
+            .enumerate()
+            .map(|(i, &len)| std::vec![i as u8; len])
+            .collect();
+        assert_eq!(replayed, expected);
+    }
+}
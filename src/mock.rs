@@ -0,0 +1,741 @@
+//! A simulated W25Q256JV for the tests: an `SpiDevice` that records every transaction and executes the commands
+//! against an in-memory model of the chip, plus recording pins and a recording delay.
+
+extern crate std;
+
+use super::*;
+use core::convert::Infallible;
+use embedded_hal::spi::Operation;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::vec::Vec;
+
+/// Shared handle to the simulated chip, kept by the test to inspect and manipulate it.
+pub type Sim = Rc<RefCell<Chip>>;
+
+/// The driver on top of the simulated chip.
+pub type Flash<DELAY = NoDelay> = W25q256jv<Spi, Pin, Pin, DELAY>;
+
+/// Creates a simulated chip and a driver talking to it.
+pub fn flash() -> (Sim, Flash) {
+    let sim = Sim::default();
+    let flash = W25q256jv::new(Spi(sim.clone()), Pin::hold(&sim), Pin::wp(&sim)).unwrap();
+    sim.borrow_mut().log.clear();
+
+    (sim, flash)
+}
+
+/// Creates a simulated chip and a driver talking to it that uses a recording delay.
+pub fn flash_with_delay(inter_command_delay_ns: u32) -> (Sim, Flash<Delay>) {
+    let (sim, flash) = flash();
+    let flash = flash.with_inter_command_delay(Delay(sim.clone()), inter_command_delay_ns);
+
+    (sim, flash)
+}
+
+/// Something that happened on the bus or the pins.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A transaction with the bytes written to the chip and the number of bytes only read from it.
+    Transaction { tx: Vec<u8>, read_len: usize },
+    /// A delay of the provided number of nanoseconds.
+    Delay(u64),
+    /// The hold pin was set.
+    Hold(PinState),
+    /// The write protect pin was set.
+    Wp(PinState),
+}
+
+/// Flips bits of bytes read from the memory array a limited number of times, e.g. to simulate a glitching bus.
+#[derive(Debug, Clone, Copy)]
+pub struct ReadFault {
+    pub address: u32,
+    pub xor: u8,
+    /// Only applies to reads with this opcode, if set.
+    pub opcode: Option<u8>,
+    /// Number of transactions the fault applies to.
+    pub remaining: usize,
+}
+
+/// Model of the chip. Erased pages of the memory array aren't stored.
+#[derive(Debug)]
+pub struct Chip {
+    pub log: Vec<Event>,
+    pages: BTreeMap<u32, [u8; PAGE_SIZE as usize]>,
+    pub security_registers: [[u8; SECURITY_REGISTER_SIZE as usize]; 3],
+    /// Current values of the status registers, without BUSY, WEL and SUS.
+    pub status_registers: [u8; 3],
+    /// Non-volatile values of the status registers, restored on reset.
+    non_volatile: [u8; 3],
+    write_enabled: bool,
+    volatile_write_enabled: bool,
+    reset_enabled: bool,
+    /// Number of status polls every program or erase stays busy for.
+    pub busy_polls: u32,
+    busy: u32,
+    /// Remaining busy polls of the suspended operation.
+    suspended: Option<u32>,
+    pub powered_down: bool,
+    /// The chip is left in the continuous read mode of a Fast Read Quad I/O: the first byte of a transaction
+    /// is taken as an address, not as a command, until ones are clocked in as mode bits.
+    pub continuous_read: bool,
+    /// The chip lost power: every transaction fails and nothing is executed.
+    pub power_lost: bool,
+    pub jedec_id: [u8; 3],
+    /// JEDEC IDs returned once each before `jedec_id`, e.g. to simulate a glitch.
+    pub jedec_id_glitches: Vec<[u8; 3]>,
+    pub unique_id: [u8; 8],
+    pub sfdp: Vec<u8>,
+    /// Write Enable is ignored.
+    pub ignore_write_enable: bool,
+    /// Enter 4-Byte Address Mode is ignored.
+    pub ignore_address_mode: bool,
+    /// Status register writes are ignored, as if the registers were protected.
+    pub ignore_status_register_writes: bool,
+    pub read_faults: Vec<ReadFault>,
+    /// Bits of the memory array stuck at a value: the address, the mask of the stuck bits and their value.
+    pub stuck_bits: Vec<(u32, u8, u8)>,
+    /// Fails the n-th (zero-based) transaction with the opcode without executing it.
+    pub fail_transaction: Option<(u8, usize)>,
+    /// Cuts the power after the n-th (zero-based) transaction with the opcode was executed.
+    pub power_loss_after: Option<(u8, usize)>,
+}
+
+impl Default for Chip {
+    fn default() -> Self {
+        Self {
+            log: Vec::new(),
+            pages: BTreeMap::new(),
+            security_registers: [[0xFF; SECURITY_REGISTER_SIZE as usize]; 3],
+            status_registers: [0; 3],
+            non_volatile: [0; 3],
+            write_enabled: false,
+            volatile_write_enabled: false,
+            reset_enabled: false,
+            busy_polls: 1,
+            busy: 0,
+            suspended: None,
+            powered_down: false,
+            continuous_read: false,
+            power_lost: false,
+            jedec_id: JEDEC_ID,
+            jedec_id_glitches: Vec::new(),
+            unique_id: [0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF],
+            sfdp: Vec::new(),
+            ignore_write_enable: false,
+            ignore_address_mode: false,
+            ignore_status_register_writes: false,
+            read_faults: Vec::new(),
+            stuck_bits: Vec::new(),
+            fail_transaction: None,
+            power_loss_after: None,
+        }
+    }
+}
+
+impl Chip {
+    /// Returns the bytes written in every transaction, in order.
+    pub fn frames(&self) -> Vec<Vec<u8>> {
+        self.log
+            .iter()
+            .filter_map(|event| match event {
+                Event::Transaction { tx, .. } => Some(tx.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the bytes written in every transaction starting with the opcode, in order.
+    pub fn commands(&self, opcode: u8) -> Vec<Vec<u8>> {
+        self.frames()
+            .into_iter()
+            .filter(|frame| frame.first() == Some(&opcode))
+            .collect()
+    }
+
+    /// Returns the opcodes of all transactions, in order.
+    pub fn opcodes(&self) -> Vec<u8> {
+        self.frames()
+            .iter()
+            .filter_map(|frame| frame.first().copied())
+            .collect()
+    }
+
+    /// Returns the number of transactions starting with the opcode.
+    pub fn count(&self, opcode: u8) -> usize {
+        self.commands(opcode).len()
+    }
+
+    /// Returns the number of transactions.
+    pub fn transactions(&self) -> usize {
+        self.frames().len()
+    }
+
+    /// Returns the address of every read (0x13) transaction, in order.
+    pub fn read_addresses(&self) -> Vec<u32> {
+        self.commands(0x13)
+            .iter()
+            .map(|frame| u32::from_be_bytes([frame[1], frame[2], frame[3], frame[4]]))
+            .collect()
+    }
+
+    /// Returns the address of every page program (0x12) transaction, in order.
+    pub fn program_addresses(&self) -> Vec<u32> {
+        self.commands(0x12)
+            .iter()
+            .map(|frame| u32::from_be_bytes([frame[1], frame[2], frame[3], frame[4]]))
+            .collect()
+    }
+
+    /// Returns the durations of all delays in nanoseconds, in order.
+    pub fn delays(&self) -> Vec<u64> {
+        self.log
+            .iter()
+            .filter_map(|event| match event {
+                Event::Delay(ns) => Some(*ns),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the contents of the memory array, as stored without any faults.
+    pub fn memory(&self, address: u32, len: usize) -> Vec<u8> {
+        (address..address + len as u32)
+            .map(|address| self.byte(address))
+            .collect()
+    }
+
+    /// Programs the memory array directly, bypassing the bus.
+    pub fn set_memory(&mut self, address: u32, data: &[u8]) {
+        for (address, &byte) in (address..).zip(data) {
+            *self.byte_mut(address) = byte;
+        }
+    }
+
+    /// Makes the chip busy for the provided number of status polls, as if an operation was running.
+    pub fn set_busy(&mut self, polls: u32) {
+        self.busy = polls;
+    }
+
+    /// Returns whether the chip is busy with an operation.
+    pub fn is_busy(&self) -> bool {
+        self.busy > 0
+    }
+
+    fn byte(&self, address: u32) -> u8 {
+        let address = address % CAPACITY;
+        self.pages
+            .get(&(address / PAGE_SIZE))
+            .map_or(0xFF, |page| page[(address % PAGE_SIZE) as usize])
+    }
+
+    fn byte_mut(&mut self, address: u32) -> &mut u8 {
+        let address = address % CAPACITY;
+        &mut self
+            .pages
+            .entry(address / PAGE_SIZE)
+            .or_insert([0xFF; PAGE_SIZE as usize])[(address % PAGE_SIZE) as usize]
+    }
+
+    fn erase(&mut self, address: u32, len: u32) {
+        let first = address / PAGE_SIZE;
+        let pages: Vec<u32> = self
+            .pages
+            .range(first..first + len / PAGE_SIZE)
+            .map(|(page, _)| *page)
+            .collect();
+
+        for page in pages {
+            self.pages.remove(&page);
+        }
+    }
+
+    fn status_register(&self, index: usize) -> u8 {
+        match index {
+            0 => {
+                (self.status_registers[0] & 0xFC)
+                    | ((self.write_enabled as u8) << 1)
+                    | (self.is_busy() as u8)
+            }
+            1 => (self.status_registers[1] & 0x7F) | ((self.suspended.is_some() as u8) << 7),
+            _ => self.status_registers[2],
+        }
+    }
+
+    fn four_byte_address_mode(&self) -> bool {
+        self.status_registers[2] & 0x01 != 0
+    }
+
+    /// Address of the security register command in the frame, and its length.
+    fn security_register_address(&self, rx: &[u8]) -> Option<(usize, u32)> {
+        let len = if self.four_byte_address_mode() { 4 } else { 3 };
+        if rx.len() < 1 + len {
+            return None;
+        }
+
+        let address = rx[1..1 + len]
+            .iter()
+            .fold(0, |address, &byte| (address << 8) | byte as u32);
+        let register = ((address >> 12) & 0x03) as usize;
+        if register == 0 {
+            return None;
+        }
+
+        Some((register - 1, address & 0xFF))
+            .map(|(register, offset)| (1 + len, register as u32 * 0x100 + offset))
+    }
+
+    /// Fills `out` with the bytes the chip shifts out at positions `pos..` of the transaction so far.
+    fn output(&mut self, rx: &[u8], pos: usize, out: &mut [u8], hits: &mut Vec<usize>) {
+        out.fill(0xFF);
+
+        let Some(&command) = rx.first() else {
+            return;
+        };
+        if self.continuous_read || (self.powered_down && command != 0xAB) {
+            return;
+        }
+        if self.is_busy() && !matches!(command, 0x05 | 0x35 | 0x15) {
+            return;
+        }
+
+        let address = |len: usize| {
+            rx.get(1..1 + len).map(|bytes| {
+                bytes
+                    .iter()
+                    .fold(0_u32, |address, &byte| (address << 8) | byte as u32)
+            })
+        };
+
+        for (i, byte) in out.iter_mut().enumerate() {
+            let p = pos + i;
+            if p == 0 {
+                continue;
+            }
+
+            *byte = match command {
+                0x05 => self.status_register(0),
+                0x35 => self.status_register(1),
+                0x15 => self.status_register(2),
+                0x9F => {
+                    let id = self.jedec_id_glitches.first().unwrap_or(&self.jedec_id);
+                    id.get(p - 1).copied().unwrap_or(0xFF)
+                }
+                0x4B => {
+                    let dummy = if self.four_byte_address_mode() { 5 } else { 4 };
+                    p.checked_sub(1 + dummy)
+                        .and_then(|index| self.unique_id.get(index))
+                        .copied()
+                        .unwrap_or(0xFF)
+                }
+                0x5A => match (address(3), p.checked_sub(5)) {
+                    (Some(address), Some(index)) => self
+                        .sfdp
+                        .get(address as usize + index)
+                        .copied()
+                        .unwrap_or(0xFF),
+                    _ => 0xFF,
+                },
+                0xAB if p >= 4 => DEVICE_ID,
+                0x48 => match self.security_register_address(rx) {
+                    Some((len, address)) if p > len => {
+                        let register = (address / 0x100) as usize;
+                        let offset = (address as usize + p - len - 1) % 0x100;
+                        self.security_registers[register][offset]
+                    }
+                    _ => 0xFF,
+                },
+                _ => 0xFF,
+            };
+        }
+
+        // Reads of the memory array
+        let start: usize = match command {
+            0x13 => 5,
+            0x0C => 6,
+            _ => return,
+        };
+        let Some(address) = address(4) else {
+            return;
+        };
+        let skip = start.saturating_sub(pos);
+        if skip < out.len() {
+            let first = address.wrapping_add((pos + skip - start) as u32) % CAPACITY;
+            self.read_memory(first, &mut out[skip..], command, hits);
+        }
+    }
+
+    /// Fills `out` with the memory array from `address` on, applying the stuck bits and read faults.
+    fn read_memory(&self, address: u32, out: &mut [u8], opcode: u8, hits: &mut Vec<usize>) {
+        let mut done = 0;
+        while done < out.len() {
+            let address = (address + done as u32) % CAPACITY;
+            let offset = (address % PAGE_SIZE) as usize;
+            let len = core::cmp::min(PAGE_SIZE as usize - offset, out.len() - done);
+
+            let out = &mut out[done..done + len];
+            match self.pages.get(&(address / PAGE_SIZE)) {
+                Some(page) => out.copy_from_slice(&page[offset..offset + len]),
+                None => out.fill(0xFF),
+            }
+            done += len;
+        }
+
+        let len = out.len();
+        let position = |fault_address: u32| {
+            let position = ((fault_address + CAPACITY - address) % CAPACITY) as usize;
+            (position < len).then_some(position)
+        };
+
+        for &(stuck, mask, value) in &self.stuck_bits {
+            if let Some(position) = position(stuck) {
+                out[position] = (out[position] & !mask) | (value & mask);
+            }
+        }
+
+        for (index, fault) in self.read_faults.iter().enumerate() {
+            if fault.remaining == 0
+                || fault
+                    .opcode
+                    .is_some_and(|fault_opcode| fault_opcode != opcode)
+            {
+                continue;
+            }
+            if let Some(position) = position(fault.address) {
+                out[position] ^= fault.xor;
+                hits.push(index);
+            }
+        }
+    }
+
+    /// Executes the command of a finished transaction, as the chip does when chip select is deasserted.
+    fn execute(&mut self, rx: &[u8]) {
+        let Some(&command) = rx.first() else {
+            return;
+        };
+
+        if self.continuous_read {
+            if command == 0xFF {
+                self.continuous_read = false;
+            }
+            return;
+        }
+
+        if self.powered_down {
+            if command == 0xAB {
+                self.powered_down = false;
+            }
+            return;
+        }
+
+        let reset_enabled = core::mem::take(&mut self.reset_enabled);
+
+        if self.is_busy() {
+            match command {
+                0x05 => {
+                    self.busy -= 1;
+                    if self.busy == 0 {
+                        self.write_enabled = false;
+                    }
+                }
+                0x75 if self.suspended.is_none() => {
+                    self.suspended = Some(self.busy);
+                    self.busy = 0;
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        let address = || u32::from_be_bytes([rx[1], rx[2], rx[3], rx[4]]);
+
+        match command {
+            0x06 if !self.ignore_write_enable => self.write_enabled = true,
+            0x04 => self.write_enabled = false,
+            0x50 => self.volatile_write_enabled = true,
+            0x01 | 0x31 | 0x11 if rx.len() >= 2 => {
+                let volatile = !self.write_enabled && self.volatile_write_enabled;
+                if (self.write_enabled || self.volatile_write_enabled)
+                    && !self.ignore_status_register_writes
+                {
+                    let values: &[(usize, u8)] = match command {
+                        0x01 if rx.len() >= 3 => &[(0, rx[1]), (1, rx[2])],
+                        0x01 => &[(0, rx[1])],
+                        0x31 => &[(1, rx[1])],
+                        _ => &[(2, rx[1])],
+                    };
+
+                    for &(index, value) in values {
+                        let old = self.status_registers[index];
+                        let new = match index {
+                            0 => value & 0xFC,
+                            // The lock bits are one-time programmable
+                            1 => (value & 0x7B) | (old & 0x38),
+                            _ => (value & 0xE6) | (old & 0x01),
+                        };
+                        self.status_registers[index] = new;
+                        if !volatile {
+                            self.non_volatile[index] = new;
+                        }
+                    }
+                }
+
+                self.volatile_write_enabled = false;
+                if !volatile && self.write_enabled {
+                    self.busy = self.busy_polls;
+                    if self.busy == 0 {
+                        self.write_enabled = false;
+                    }
+                }
+            }
+            0x12 if self.write_enabled && rx.len() >= 5 => {
+                let address = address() % CAPACITY;
+                let page = self
+                    .pages
+                    .entry(address / PAGE_SIZE)
+                    .or_insert([0xFF; PAGE_SIZE as usize]);
+                for (i, &byte) in rx[5..].iter().enumerate() {
+                    page[(address as usize + i) % PAGE_SIZE as usize] &= byte;
+                }
+                self.start_operation();
+            }
+            0x21 | 0x5C | 0xDC if self.write_enabled && rx.len() >= 5 => {
+                let len = match command {
+                    0x21 => SECTOR_SIZE,
+                    0x5C => BLOCK_32K_SIZE,
+                    _ => BLOCK_64K_SIZE,
+                };
+                self.erase(address() / len * len, len);
+                self.start_operation();
+            }
+            0xC7 | 0x60 if self.write_enabled => {
+                self.pages.clear();
+                self.start_operation();
+            }
+            0x42 | 0x44 if self.write_enabled => {
+                if let Some((len, address)) = self.security_register_address(rx) {
+                    let register = (address / 0x100) as usize;
+                    let locked = self.status_registers[1] & (0x08 << register) != 0;
+
+                    if !locked {
+                        if command == 0x44 {
+                            self.security_registers[register] = [0xFF; 256];
+                        } else {
+                            for (i, &byte) in rx[len..].iter().enumerate() {
+                                let offset = (address as usize + i) % 0x100;
+                                self.security_registers[register][offset] &= byte;
+                            }
+                        }
+                    }
+                }
+                self.start_operation();
+            }
+            0x66 => self.reset_enabled = true,
+            0x99 if reset_enabled => {
+                self.write_enabled = false;
+                self.volatile_write_enabled = false;
+                self.suspended = None;
+                self.status_registers = self.non_volatile;
+                // The address mode after a reset is the power-up address mode ADP
+                self.status_registers[2] =
+                    (self.status_registers[2] & !0x01) | ((self.status_registers[2] >> 1) & 0x01);
+            }
+            0xB7 if !self.ignore_address_mode => self.status_registers[2] |= 0x01,
+            0xE9 => self.status_registers[2] &= !0x01,
+            0xB9 => self.powered_down = true,
+            0x7A => {
+                if let Some(busy) = self.suspended.take() {
+                    self.busy = busy;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn start_operation(&mut self) {
+        self.busy = self.busy_polls;
+        if self.busy == 0 {
+            self.write_enabled = false;
+        }
+    }
+
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), MockError> {
+        let mut rx = Vec::new();
+        let mut tx = Vec::new();
+        let mut read_len = 0;
+        let mut hits = Vec::new();
+
+        for operation in operations.iter_mut() {
+            match operation {
+                Operation::Write(buf) => {
+                    rx.extend_from_slice(buf);
+                    tx.extend_from_slice(buf);
+                }
+                Operation::Read(buf) => {
+                    self.output(&rx, rx.len(), buf, &mut hits);
+                    rx.resize(rx.len() + buf.len(), 0);
+                    read_len += buf.len();
+                }
+                Operation::Transfer(read, write) => {
+                    let pos = rx.len();
+                    rx.extend_from_slice(write);
+                    tx.extend_from_slice(write);
+                    if read.len() > write.len() {
+                        rx.resize(pos + read.len(), 0);
+                    }
+                    self.output(&rx, pos, read, &mut hits);
+                }
+                Operation::TransferInPlace(buf) => {
+                    let pos = rx.len();
+                    rx.extend_from_slice(buf);
+                    tx.extend_from_slice(buf);
+                    self.output(&rx, pos, buf, &mut hits);
+                }
+                Operation::DelayNs(_) => {}
+            }
+        }
+
+        let opcode = rx.first().copied();
+        self.log.push(Event::Transaction { tx, read_len });
+
+        if self.power_lost {
+            return Err(MockError);
+        }
+
+        if let (Some((fail_opcode, n)), Some(opcode)) = (self.fail_transaction, opcode) {
+            if fail_opcode == opcode {
+                if n == 0 {
+                    self.fail_transaction = None;
+                    return Err(MockError);
+                }
+                self.fail_transaction = Some((fail_opcode, n - 1));
+            }
+        }
+
+        hits.dedup();
+        for index in hits {
+            self.read_faults[index].remaining -= 1;
+        }
+        if opcode == Some(0x9F) && !self.jedec_id_glitches.is_empty() {
+            self.jedec_id_glitches.remove(0);
+        }
+
+        self.execute(&rx);
+
+        if let (Some((loss_opcode, n)), Some(opcode)) = (self.power_loss_after, opcode) {
+            if loss_opcode == opcode {
+                if n == 0 {
+                    self.power_loss_after = None;
+                    self.power_lost = true;
+                } else {
+                    self.power_loss_after = Some((loss_opcode, n - 1));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MockError;
+
+impl embedded_hal::spi::Error for MockError {
+    fn kind(&self) -> embedded_hal::spi::ErrorKind {
+        embedded_hal::spi::ErrorKind::Other
+    }
+}
+
+/// The bus to the simulated chip.
+pub struct Spi(pub Sim);
+
+impl embedded_hal::spi::ErrorType for Spi {
+    type Error = MockError;
+}
+
+impl embedded_hal_async::spi::SpiDevice for Spi {
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        self.0.borrow_mut().transaction(operations)
+    }
+}
+
+impl embedded_hal::spi::SpiDevice for Spi {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        self.0.borrow_mut().transaction(operations)
+    }
+}
+
+/// A pin that records its states in the log of the simulated chip.
+pub struct Pin {
+    sim: Sim,
+    hold: bool,
+}
+
+impl Pin {
+    pub fn hold(sim: &Sim) -> Self {
+        Self {
+            sim: sim.clone(),
+            hold: true,
+        }
+    }
+
+    pub fn wp(sim: &Sim) -> Self {
+        Self {
+            sim: sim.clone(),
+            hold: false,
+        }
+    }
+}
+
+impl embedded_hal::digital::ErrorType for Pin {
+    type Error = Infallible;
+}
+
+impl OutputPin for Pin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.set_state(PinState::Low)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.set_state(PinState::High)
+    }
+
+    fn set_state(&mut self, state: PinState) -> Result<(), Self::Error> {
+        let event = if self.hold {
+            Event::Hold(state)
+        } else {
+            Event::Wp(state)
+        };
+        self.sim.borrow_mut().log.push(event);
+
+        Ok(())
+    }
+}
+
+/// A delay that records its duration in the log of the simulated chip and yields once instead of waiting.
+pub struct Delay(pub Sim);
+
+impl Delay {
+    async fn record(&mut self, ns: u64) {
+        self.0.borrow_mut().log.push(Event::Delay(ns));
+        embassy_futures::yield_now().await;
+    }
+}
+
+impl DelayNs for Delay {
+    async fn delay_ns(&mut self, ns: u32) {
+        self.record(ns as u64).await;
+    }
+
+    async fn delay_us(&mut self, us: u32) {
+        self.record(us as u64 * 1_000).await;
+    }
+
+    async fn delay_ms(&mut self, ms: u32) {
+        self.record(ms as u64 * 1_000_000).await;
+    }
+}
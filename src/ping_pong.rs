@@ -0,0 +1,378 @@
+use super::*;
+use core::fmt::Debug;
+use embedded_hal::digital::OutputPin;
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::spi::SpiDevice;
+
+/// Length of the sequence number in front of the data of a slot.
+const SEQUENCE_LEN: u32 = 4;
+/// Length of the checksum behind the data of a slot.
+const CRC_LEN: u32 = 4;
+
+/// Stores a small, frequently updated record, like a counter or a pointer, without wearing out a single sector.
+///
+/// Every update is written to a fresh slot in one of two sectors. Only when a sector is full, the other one is
+/// erased and used from then on. The latest record is the valid slot with the highest sequence number. A slot
+/// consists of a little-endian 4-byte sequence number, the record and its [crc32::Crc32] checksum over both
+/// as 4 little-endian bytes, so a write torn by a power loss is skipped and the previous record is found instead.
+/// Sequence numbers wrap around, skipping u32::MAX, which can't be told apart from an erased slot.
+pub struct PingPongStore<'a, SPI, HOLD, WP, DELAY = NoDelay> {
+    flash: &'a mut W25q256jv<SPI, HOLD, WP, DELAY>,
+    sectors: [u32; 2],
+    record_len: u32,
+    /// Sequence number of the latest record.
+    sequence: u32,
+    /// Sector (0 or 1) and slot of the latest record, if there is any.
+    latest: Option<(usize, u32)>,
+    /// Sector (0 or 1) and slot the next record will be written to.
+    next: (usize, u32),
+}
+
+impl<'a, SPI, S: Debug, P: Debug, HOLD, WP, DELAY> PingPongStore<'a, SPI, HOLD, WP, DELAY>
+where
+    SPI: SpiDevice<Error = S>,
+    HOLD: OutputPin<Error = P>,
+    WP: OutputPin<Error = P>,
+    DELAY: DelayNs,
+    S: Debug,
+    P: Debug,
+{
+    /// Opens the store in the provided sectors, scanning them for the latest record.
+    /// Sectors that were never used by the store are erased when the store first writes to them.
+    ///
+    /// # Arguments
+    /// * `flash` - The flash chip to store the record on.
+    /// * `sectors` - The indices of the two sectors to alternate between.
+    /// * `record_len` - The length of the record. At most SECTOR_SIZE / 2 - 8 bytes.
+    pub async fn open(
+        flash: &'a mut W25q256jv<SPI, HOLD, WP, DELAY>,
+        sectors: [u32; 2],
+        record_len: u32,
+    ) -> Result<Self, Error<S, P>> {
+        if sectors[0] >= N_SECTORS || sectors[1] >= N_SECTORS || sectors[0] == sectors[1] {
+            return Err(Error::OutOfBounds);
+        }
+        if record_len > SECTOR_SIZE / 2 - SEQUENCE_LEN - CRC_LEN {
+            return Err(Error::OutOfBounds);
+        }
+
+        let mut store = Self {
+            flash,
+            sectors,
+            record_len,
+            sequence: 0,
+            latest: None,
+            next: (0, 0),
+        };
+
+        for sector in 0..2 {
+            // Slots are filled in order, so the first erased slot ends the used part of the sector
+            let mut used = 0;
+            while used < store.slots_per_sector() {
+                match store.read_slot(sector, used, None).await? {
+                    SlotState::Erased => break,
+                    SlotState::Valid(sequence) => {
+                        // Sequence numbers wrap around, so compare them by their distance
+                        if store.latest.is_none()
+                            || sequence.wrapping_sub(store.sequence) as i32 > 0
+                        {
+                            store.sequence = sequence;
+                            store.latest = Some((sector, used));
+                        }
+                    }
+                    SlotState::Invalid => {}
+                }
+                used += 1;
+            }
+
+            if store.latest.is_some_and(|(latest, _)| latest == sector) {
+                store.next = (sector, used);
+            }
+        }
+
+        Ok(store)
+    }
+
+    /// Writes a new version of the record.
+    ///
+    /// # Arguments
+    /// * `data` - The record. Must be exactly as long as the record length the store was opened with,
+    ///   otherwise [Error::OutOfBounds] is returned.
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), Error<S, P>> {
+        if data.len() != self.record_len as usize {
+            return Err(Error::OutOfBounds);
+        }
+
+        let (mut sector, mut slot) = self.next;
+        if slot == self.slots_per_sector() {
+            (sector, slot) = (1 - sector, 0);
+        }
+        if slot == 0 {
+            self.flash.erase_sector(self.sectors[sector]).await?;
+        }
+
+        let mut sequence = if self.latest.is_some() {
+            self.sequence.wrapping_add(1)
+        } else {
+            0
+        };
+        // An all-ones sequence number would read as an erased slot
+        if sequence == u32::MAX {
+            sequence = 0;
+        }
+        let sequence = sequence.to_le_bytes();
+
+        let mut crc = crc32::Crc32::new();
+        crc.update(&sequence);
+        crc.update(data);
+
+        let address = self.slot_address(sector, slot);
+        self.flash.write(address, &sequence).await?;
+        self.flash.write(address + SEQUENCE_LEN, data).await?;
+        self.flash
+            .write(
+                address + SEQUENCE_LEN + self.record_len,
+                &crc.finalize().to_le_bytes(),
+            )
+            .await?;
+
+        self.sequence = u32::from_le_bytes(sequence);
+        self.latest = Some((sector, slot));
+        self.next = (sector, slot + 1);
+
+        Ok(())
+    }
+
+    /// Reads the latest version of the record.
+    ///
+    /// # Arguments
+    /// * `buf` - Slice that is going to be filled with the record. Must be at least as long as the record.
+    ///
+    /// Returns false if no record has been written yet.
+    pub async fn read_latest(&mut self, buf: &mut [u8]) -> Result<bool, Error<S, P>> {
+        if buf.len() < self.record_len as usize {
+            return Err(Error::BufferTooSmall);
+        }
+
+        let Some((sector, slot)) = self.latest else {
+            return Ok(false);
+        };
+
+        let buf = &mut buf[..self.record_len as usize];
+        match self.read_slot(sector, slot, Some(buf)).await? {
+            SlotState::Valid(_) => Ok(true),
+            _ => Err(Error::ReadbackFail),
+        }
+    }
+
+    fn slots_per_sector(&self) -> u32 {
+        SECTOR_SIZE / (SEQUENCE_LEN + self.record_len + CRC_LEN)
+    }
+
+    fn slot_address(&self, sector: usize, slot: u32) -> u32 {
+        self.sectors[sector] * SECTOR_SIZE + slot * (SEQUENCE_LEN + self.record_len + CRC_LEN)
+    }
+
+    /// Reads a slot and checks its checksum. The record is read into `buf` if provided,
+    /// otherwise it is read in chunks just for the checksum.
+    async fn read_slot(
+        &mut self,
+        sector: usize,
+        slot: u32,
+        buf: Option<&mut [u8]>,
+    ) -> Result<SlotState, Error<S, P>> {
+        let address = self.slot_address(sector, slot);
+
+        let mut sequence = [0; SEQUENCE_LEN as usize];
+        self.flash.read(address, &mut sequence).await?;
+        if sequence == [0xFF; SEQUENCE_LEN as usize] {
+            return Ok(SlotState::Erased);
+        }
+
+        let mut crc = crc32::Crc32::new();
+        crc.update(&sequence);
+
+        let data_address = address + SEQUENCE_LEN;
+        match buf {
+            Some(buf) => {
+                self.flash.read_hashed(data_address, buf, &mut crc).await?;
+            }
+            None => {
+                let mut chunk = [0; 64];
+                for offset in (0..self.record_len).step_by(chunk.len()) {
+                    let len = core::cmp::min(chunk.len() as u32, self.record_len - offset);
+                    self.flash
+                        .read_hashed(data_address + offset, &mut chunk[..len as usize], &mut crc)
+                        .await?;
+                }
+            }
+        }
+
+        let mut stored = [0; CRC_LEN as usize];
+        self.flash
+            .read(data_address + self.record_len, &mut stored)
+            .await?;
+
+        if crc.finalize() == u32::from_le_bytes(stored) {
+            Ok(SlotState::Valid(u32::from_le_bytes(sequence)))
+        } else {
+            Ok(SlotState::Invalid)
+        }
+    }
+}
+
+/// State of a slot of a [PingPongStore].
+enum SlotState {
+    Erased,
+    /// The slot holds a record with the contained sequence number.
+    Valid(u32),
+    /// The slot was written, but the write was torn.
+    Invalid,
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::mock;
+    use embassy_futures::block_on;
+
+    const SECTORS: [u32; 2] = [20, 21];
+    /// Long enough for only two slots per sector.
+    const LONG_RECORD: u32 = SECTOR_SIZE / 2 - SEQUENCE_LEN - CRC_LEN;
+
+    /// Stores a valid slot with the sequence number and record directly on the chip.
+    fn store_slot(sim: &mock::Sim, address: u32, sequence: u32, data: &[u8]) {
+        let mut crc = crc32::Crc32::new();
+        crc.update(&sequence.to_le_bytes());
+        crc.update(data);
+
+        let mut chip = sim.borrow_mut();
+        chip.set_memory(address, &sequence.to_le_bytes());
+        chip.set_memory(address + SEQUENCE_LEN, data);
+        chip.set_memory(
+            address + SEQUENCE_LEN + data.len() as u32,
+            &crc.finalize().to_le_bytes(),
+        );
+    }
+
+    #[test]
+    fn latest_record_survives_reopening() {
+        let (_, mut flash) = mock::flash();
+
+        {
+            let mut store = block_on(PingPongStore::open(&mut flash, SECTORS, 4)).unwrap();
+            assert!(!block_on(store.read_latest(&mut [0; 4])).unwrap());
+            for counter in 0..10u32 {
+                block_on(store.write(&counter.to_le_bytes())).unwrap();
+            }
+        }
+
+        let mut store = block_on(PingPongStore::open(&mut flash, SECTORS, 4)).unwrap();
+        let mut buf = [0; 4];
+        assert!(block_on(store.read_latest(&mut buf)).unwrap());
+        assert_eq!(u32::from_le_bytes(buf), 9);
+    }
+
+    #[test]
+    fn write_switches_sectors_when_full() {
+        let (sim, mut flash) = mock::flash();
+        let record = |value: u8| [value; LONG_RECORD as usize];
+        let mut store = block_on(PingPongStore::open(&mut flash, SECTORS, LONG_RECORD)).unwrap();
+
+        for value in 0..3 {
+            block_on(store.write(&record(value))).unwrap();
+        }
+        // The third record is the first one in the second sector
+        assert_eq!(
+            sim.borrow()
+                .memory(SECTORS[1] * SECTOR_SIZE + SEQUENCE_LEN, 1),
+            [2]
+        );
+
+        for value in 3..5 {
+            block_on(store.write(&record(value))).unwrap();
+        }
+        // The fifth record wraps around to the first sector, which is erased first
+        let erased: std::vec::Vec<u32> = sim
+            .borrow()
+            .commands(0x21)
+            .iter()
+            .map(|frame| u32::from_be_bytes([frame[1], frame[2], frame[3], frame[4]]) / SECTOR_SIZE)
+            .collect();
+        assert_eq!(erased, [SECTORS[0], SECTORS[1], SECTORS[0]]);
+
+        let mut buf = [0; LONG_RECORD as usize];
+        assert!(block_on(store.read_latest(&mut buf)).unwrap());
+        assert_eq!(buf, record(4));
+    }
+
+    #[test]
+    fn open_picks_the_latest_record_across_sequence_wraparound() {
+        let (sim, mut flash) = mock::flash();
+        let slot_len = SEQUENCE_LEN + 4 + CRC_LEN;
+        let first = SECTORS[0] * SECTOR_SIZE;
+        let second = SECTORS[1] * SECTOR_SIZE;
+        store_slot(&sim, first, u32::MAX - 2, b"old1");
+        store_slot(&sim, first + slot_len, u32::MAX - 1, b"old2");
+        store_slot(&sim, second, 0, b"new1");
+        store_slot(&sim, second + slot_len, 1, b"new2");
+
+        let mut store = block_on(PingPongStore::open(&mut flash, SECTORS, 4)).unwrap();
+        let mut buf = [0; 4];
+        assert!(block_on(store.read_latest(&mut buf)).unwrap());
+        assert_eq!(&buf, b"new2");
+
+        // The next record follows the latest one
+        block_on(store.write(b"new3")).unwrap();
+        assert_eq!(
+            sim.borrow()
+                .memory(second + 2 * slot_len, SEQUENCE_LEN as usize),
+            2u32.to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn write_skips_the_all_ones_sequence_number() {
+        let (sim, mut flash) = mock::flash();
+        let slot_len = SEQUENCE_LEN + 4 + CRC_LEN;
+        let first = SECTORS[0] * SECTOR_SIZE;
+        store_slot(&sim, first, u32::MAX - 2, b"old1");
+        store_slot(&sim, first + slot_len, u32::MAX - 1, b"old2");
+
+        {
+            let mut store = block_on(PingPongStore::open(&mut flash, SECTORS, 4)).unwrap();
+            let mut buf = [0; 4];
+            assert!(block_on(store.read_latest(&mut buf)).unwrap());
+            assert_eq!(&buf, b"old2");
+
+            block_on(store.write(b"new1")).unwrap();
+        }
+        assert_eq!(
+            sim.borrow()
+                .memory(first + 2 * slot_len, SEQUENCE_LEN as usize),
+            0u32.to_le_bytes()
+        );
+
+        let mut store = block_on(PingPongStore::open(&mut flash, SECTORS, 4)).unwrap();
+        let mut buf = [0; 4];
+        assert!(block_on(store.read_latest(&mut buf)).unwrap());
+        assert_eq!(&buf, b"new1");
+    }
+
+    #[test]
+    fn write_rejects_a_record_of_the_wrong_length() {
+        let (_, mut flash) = mock::flash();
+        let mut store = block_on(PingPongStore::open(&mut flash, SECTORS, 4)).unwrap();
+
+        assert!(matches!(
+            block_on(store.write(&[0; 5])),
+            Err(Error::OutOfBounds)
+        ));
+        assert!(matches!(
+            block_on(store.write(&[0; 3])),
+            Err(Error::OutOfBounds)
+        ));
+    }
+}
@@ -0,0 +1,106 @@
+use super::*;
+use core::fmt::Debug;
+use core::ops::{Deref, DerefMut};
+use embedded_hal::digital::OutputPin;
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::spi::SpiDevice;
+
+/// Guard for a suspended erase, returned by [W25q256jv::erase_suspend].
+///
+/// Derefs to the driver, so the chip can be read while the erase is suspended.
+/// Reads must avoid the sector or block that is being erased.
+///
+/// Resume the erase with [EraseGuard::resume]. If the guard is dropped instead, the erase is
+/// resumed by the next operation that waits for the chip to be ready.
+pub struct EraseGuard<'a, SPI, HOLD, WP, DELAY = NoDelay> {
+    flash: &'a mut W25q256jv<SPI, HOLD, WP, DELAY>,
+    resumed: bool,
+}
+
+impl<'a, SPI, HOLD, WP, DELAY> EraseGuard<'a, SPI, HOLD, WP, DELAY> {
+    pub(crate) fn new(flash: &'a mut W25q256jv<SPI, HOLD, WP, DELAY>) -> Self {
+        Self {
+            flash,
+            resumed: false,
+        }
+    }
+}
+
+impl<SPI, S: Debug, P: Debug, HOLD, WP, DELAY> EraseGuard<'_, SPI, HOLD, WP, DELAY>
+where
+    SPI: SpiDevice<Error = S>,
+    HOLD: OutputPin<Error = P>,
+    WP: OutputPin<Error = P>,
+    DELAY: DelayNs,
+    S: Debug,
+    P: Debug,
+{
+    /// Resumes the suspended erase. Does not wait for the erase to complete.
+    pub async fn resume(mut self) -> Result<(), Error<S, P>> {
+        self.resumed = true;
+        self.flash.resume().await
+    }
+}
+
+impl<SPI, HOLD, WP, DELAY> Deref for EraseGuard<'_, SPI, HOLD, WP, DELAY> {
+    type Target = W25q256jv<SPI, HOLD, WP, DELAY>;
+
+    fn deref(&self) -> &Self::Target {
+        self.flash
+    }
+}
+
+impl<SPI, HOLD, WP, DELAY> DerefMut for EraseGuard<'_, SPI, HOLD, WP, DELAY> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.flash
+    }
+}
+
+impl<SPI, HOLD, WP, DELAY> Drop for EraseGuard<'_, SPI, HOLD, WP, DELAY> {
+    fn drop(&mut self) {
+        if !self.resumed {
+            self.flash.resume_pending = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock;
+    use embassy_futures::block_on;
+
+    #[test]
+    fn explicit_resume_issues_the_resume_command() {
+        let (sim, mut flash) = mock::flash();
+        // An erase that is still running
+        sim.borrow_mut().set_busy(5);
+
+        let mut guard = block_on(flash.erase_suspend()).unwrap();
+        assert!(block_on(guard.is_suspended()).unwrap());
+        assert!(!sim.borrow().is_busy());
+
+        sim.borrow_mut().log.clear();
+        block_on(guard.resume()).unwrap();
+
+        assert_eq!(sim.borrow().frames(), [[0x7A]]);
+        assert!(sim.borrow().is_busy());
+        assert!(!flash.resume_pending);
+    }
+
+    #[test]
+    fn dropped_guard_resumes_on_next_wait() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().set_busy(5);
+
+        drop(block_on(flash.erase_suspend()).unwrap());
+        assert!(flash.resume_pending);
+
+        sim.borrow_mut().log.clear();
+        block_on(flash.wait_ready()).unwrap();
+
+        let opcodes = sim.borrow().opcodes();
+        assert_eq!(opcodes[0], 0x7A);
+        assert!(opcodes[1..].iter().all(|&opcode| opcode == 0x05));
+        assert!(!flash.resume_pending);
+    }
+}
@@ -1,14 +1,23 @@
 use super::*;
 use core::fmt::Debug;
 use embedded_hal::digital::OutputPin;
+use embedded_hal_async::delay::DelayNs;
 use embedded_hal_async::spi::{Operation, SpiDevice};
 use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
 
-impl<SPI, S: Debug, P: Debug, HOLD, WP> ReadNorFlash for W25q256jv<SPI, HOLD, WP>
+/// Writable bits of status register 1. BUSY and WEL are read-only.
+const SR1_WRITABLE: u8 = 0xFC;
+/// Writable bits of status register 2. SUS and the reserved bit 2 are read-only.
+const SR2_WRITABLE: u8 = 0x7B;
+/// Writable bits of status register 3. ADS and the reserved bits 3 and 4 are read-only.
+const SR3_WRITABLE: u8 = 0xE6;
+
+impl<SPI, S: Debug, P: Debug, HOLD, WP, DELAY> ReadNorFlash for W25q256jv<SPI, HOLD, WP, DELAY>
 where
     SPI: SpiDevice<Error = S> + embedded_hal::spi::SpiDevice,
     HOLD: OutputPin<Error = P>,
     WP: OutputPin<Error = P>,
+    DELAY: DelayNs,
     S: Debug,
     P: Debug,
 {
@@ -23,11 +32,12 @@ where
     }
 }
 
-impl<SPI, S: Debug, P: Debug, HOLD, WP> NorFlash for W25q256jv<SPI, HOLD, WP>
+impl<SPI, S: Debug, P: Debug, HOLD, WP, DELAY> NorFlash for W25q256jv<SPI, HOLD, WP, DELAY>
 where
     SPI: SpiDevice<Error = S> + embedded_hal::spi::SpiDevice + embedded_hal::spi::SpiDevice,
     HOLD: OutputPin<Error = P>,
     WP: OutputPin<Error = P>,
+    DELAY: DelayNs,
     S: Debug,
     P: Debug,
 {
@@ -40,22 +50,44 @@ where
     }
 
     async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
-        self.write(offset, bytes).await
+        self.write_nor_flash(offset, bytes).await
     }
 }
 
-impl<SPI, S: Debug, P: Debug, HOLD, WP> W25q256jv<SPI, HOLD, WP>
+impl<SPI, S: Debug, P: Debug, HOLD, WP, DELAY> W25q256jv<SPI, HOLD, WP, DELAY>
 where
     SPI: SpiDevice<Error = S>,
     HOLD: OutputPin<Error = P>,
     WP: OutputPin<Error = P>,
+    DELAY: DelayNs,
     S: Debug,
     P: Debug,
 {
     /// Reads status register 1 of the flash chip.
     async fn read_status_register(&mut self) -> Result<u8, Error<S, P>> {
+        self.read_register(Command::ReadStatusRegister1).await
+    }
+
+    /// Reads status register 2 of the flash chip.
+    ///
+    /// It holds, from bit 7 to bit 0: SUS (suspended), CMP (complement protect), LB3 to LB1 (security register locks),
+    /// a reserved bit, QE (quad enable) and SRL (status register lock).
+    pub async fn read_status_register_2(&mut self) -> Result<u8, Error<S, P>> {
+        self.read_register(Command::ReadStatusRegister2).await
+    }
+
+    /// Reads status register 3 of the flash chip.
+    ///
+    /// It holds, from bit 7 to bit 0: HOLD/RST (function of the hold pin), DRV1 and DRV0 (output driver strength),
+    /// two reserved bits, WPS (write protect selection), ADP (power-up address mode) and ADS (current address mode).
+    pub async fn read_status_register_3(&mut self) -> Result<u8, Error<S, P>> {
+        self.read_register(Command::ReadStatusRegister3).await
+    }
+
+    /// Reads a single byte register using the provided read command.
+    async fn read_register(&mut self, command: Command) -> Result<u8, Error<S, P>> {
         let mut buf: [u8; 2] = [0; 2];
-        buf[0] = Command::ReadStatusRegister1 as u8;
+        buf[0] = command as u8;
 
         self.spi
             .transfer_in_place(&mut buf)
@@ -71,6 +103,83 @@ where
         Ok((self.read_status_register().await? & 0x01) != 0)
     }
 
+    /// Waits until the chip is no longer busy with a previous operation.
+    ///
+    /// The returned future can be combined with other futures, e.g. with `join` or `select`.
+    /// Note that the chip is polled continuously, which keeps the SPI bus occupied.
+    ///
+    /// If a [suspend::EraseGuard] was dropped without resuming, the suspended operation is resumed first.
+    pub async fn wait_ready(&mut self) -> Result<(), Error<S, P>> {
+        if self.resume_pending {
+            self.resume().await?;
+        }
+
+        while self.busy().await? {
+            if self.shared_bus {
+                self.inter_command_delay().await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Waits for the chip to complete an operation that typically takes `typical_us` microseconds.
+    ///
+    /// Between polls, the configured delay source waits a fraction of the typical time, so the bus isn't
+    /// hammered with status reads during long operations. Without a delay source, the chip is polled continuously.
+    async fn wait_done(&mut self, typical_us: u32) -> Result<(), Error<S, P>> {
+        let interval_us = typical_us / TimingProfile::POLLS_PER_OPERATION;
+        #[cfg(feature = "defmt")]
+        let start = self.clock.map(|clock| clock());
+
+        while self.busy().await? {
+            if interval_us > 0 {
+                self.delay.delay_us(interval_us).await;
+            } else if self.shared_bus {
+                self.inter_command_delay().await;
+            }
+        }
+        self.in_operation = false;
+
+        #[cfg(feature = "defmt")]
+        if let Some(start) = start {
+            self.log_duration(start, typical_us);
+        }
+
+        Ok(())
+    }
+
+    /// Logs the duration of an operation that started at `start` ticks of the clock, and returns it.
+    #[cfg(feature = "defmt")]
+    fn log_duration(&self, start: u32, typical_us: u32) -> u32 {
+        let end = self.clock.map_or(start, |clock| clock());
+        let ticks = end.wrapping_sub(start);
+        defmt::debug!(
+            "operation from {} to {} took {} ticks (typical {} us)",
+            start,
+            end,
+            ticks,
+            typical_us
+        );
+
+        ticks
+    }
+
+    /// Checks that no program or erase started by this driver is still running before starting another one.
+    ///
+    /// The flag is only left set if the future of an operation was dropped before it completed.
+    /// Returns [Error::Busy] if the chip is still busy with that operation, which points to a logic error in the caller.
+    async fn begin_operation(&mut self) -> Result<(), Error<S, P>> {
+        if self.in_operation {
+            if self.busy().await? {
+                return Err(Error::Busy);
+            }
+            self.in_operation = false;
+        }
+
+        Ok(())
+    }
+
     /// Sets the enable_write flag on the flash chip to true.
     /// Writes and erases to the chip only have effect when this flag is true.
     /// Each write and erase clears the flag, requiring it to be set to true again for the next command.
@@ -80,335 +189,4938 @@ where
             .await
             .map_err(Error::SpiError)?;
 
-        if !self.write_enabled().await? {
+        if !self.fast_write && !self.write_enabled().await? {
             return Err(Error::WriteEnableFail);
         }
 
         Ok(())
     }
 
-    /// The flash chip must be write-enabled for write and erase operations to work.
-    /// This function returns true while the write-enable flag is set.
-    pub async fn write_enabled(&mut self) -> Result<bool, Error<S, P>> {
-        Ok((self.read_status_register().await? & 0x02) != 0)
-    }
-
-    /// The flash chip will enter into 4-byte address mode. The factory default is 3-byte
-    /// address mode. Note that the W25Q256JV supports dedicated 4-byte address mode commands,
-    /// which take 4-byte addresses regardless of the address mode.
-    async fn enter_4_byte_address_mode(&mut self) -> Result<(), Error<S, P>> {
+    /// Clears the enable_write flag on the flash chip.
+    async fn disable_write(&mut self) -> Result<(), Error<S, P>> {
         self.spi
-            .write(&[Command::Enter4ByteAddressMode as u8])
+            .write(&[Command::WriteDisable as u8])
             .await
             .map_err(Error::SpiError)?;
 
         Ok(())
     }
 
-    /// The flash chip will exit 4-byte address mode. The factory default is 3-byte
-    /// address mode. Note that the W25Q256JV supports dedicated 4-byte address mode commands,
-    /// which take 4-byte addresses regardless of the address mode.
-    #[allow(dead_code)]
-    async fn exit_4_byte_address_mode(&mut self) -> Result<(), Error<S, P>> {
+    /// Sets the enable_write flag and reports whether the chip latched it, without performing a write or erase.
+    /// The flag is cleared again before returning.
+    ///
+    /// This is meant for diagnosing the bus and the write-enable mechanism, separately from the write and erase operations.
+    pub async fn check_write_enable_latch(&mut self) -> Result<bool, Error<S, P>> {
+        // The chip ignores the write enable command while it is busy
+        self.wait_ready().await?;
+
         self.spi
-            .write(&[Command::Exit4ByteAddressMode as u8])
+            .write(&[Command::WriteEnable as u8])
             .await
             .map_err(Error::SpiError)?;
 
-        Ok(())
+        let latched = self.write_enabled().await?;
+        self.disable_write().await?;
+
+        Ok(latched)
     }
 
-    /// Resets the chip without respect to ongoing operations. Data corruption may happen if
-    /// there is an ongoing or suspended internal Erase or Program operation
-    pub async unsafe fn reset(&mut self) -> Result<(), Error<S, P>> {
-        self.spi
-            .write(&[Command::ResetDevice as u8])
-            .await
-            .map_err(Error::SpiError)?;
-        self.spi
-            .write(&[Command::EnableReset as u8])
-            .await
-            .map_err(Error::SpiError)?;
-        Ok(())
+    /// The flash chip must be write-enabled for write and erase operations to work.
+    /// This function returns true while the write-enable flag is set.
+    pub async fn write_enabled(&mut self) -> Result<bool, Error<S, P>> {
+        Ok((self.read_status_register().await? & 0x02) != 0)
     }
 
-    /// Reads a chunk of bytes from the flash chip.
-    /// The number of bytes read is equal to the length of the buf slice.
-    /// The first byte is read from the provided address. This address is then incremented for each following byte.
-    ///
-    /// # Arguments
-    /// * `address` - Address where the first byte of the buf will be read.
-    /// * `buf` - Slice that is going to be filled with the read bytes.
-    pub async fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error<S, P>> {
-        if address + buf.len() as u32 > CAPACITY {
-            return Err(Error::OutOfBounds);
-        }
+    /// Reads whether the chip is busy, whether the write-enable latch is set and which region is protected.
+    /// The busy and write-enable flags come from a single read of status register 1, which is followed by
+    /// a read of status register 2. The block protect bits of status register 1 alone don't tell the protected
+    /// region: the complement protect bit CMP in status register 2 inverts it.
+    pub async fn write_readiness(&mut self) -> Result<WriteReadiness, Error<S, P>> {
+        let sr1 = self.read_status_register().await?;
+        let sr2 = self.read_status_register_2().await?;
 
-        self.spi
-            .transaction(&mut [
-                Operation::Write(&command_and_address(
-                    Command::ReadDataWith4ByteAddress as u8,
-                    address,
-                )),
-                Operation::Read(buf),
-            ])
+        Ok(WriteReadiness::from_status_registers(sr1, sr2))
+    }
+
+    /// Writes a status register using the provided write command.
+    ///
+    /// Status register writes are non-volatile: they wear the chip and keep the chip busy for up to 15ms.
+    async fn write_status_register(
+        &mut self,
+        command: Command,
+        value: u8,
+    ) -> Result<(), Error<S, P>> {
+        self.write_status_register_bytes(&[command as u8, value])
             .await
-            .map_err(Error::SpiError)?;
+    }
 
-        Ok(())
+    /// Sends a status register write command frame, see [W25q256jv::write_status_register].
+    async fn write_status_register_bytes(&mut self, frame: &[u8]) -> Result<(), Error<S, P>> {
+        self.begin_operation().await?;
+        self.wait_ready().await?;
+        self.enable_write().await?;
+
+        self.spi.write(frame).await.map_err(Error::SpiError)?;
+
+        // typical 10ms, max 15ms
+        self.wait_ready().await
     }
 
-    /// Writes a chunk of bytes to the flash chip.
-    /// The first byte is written to the provided address. This address is then incremented for each following byte.
+    /// Writes status register 1, which holds the block protection bits and the Status Register Protect bit.
     ///
-    /// This function will wait for any ongoing operations to complete before starting the write operation,
-    /// to prevent data corruption.
+    /// Some parts of the family also take status register 2 as a second byte of this command, and clear it when
+    /// it is left out. To not disable quad mode by accident, the current value of status register 2 is read
+    /// and written back along with the new value.
     ///
-    /// As this is a NOR-flash chip, the write operation will only change bits from 1 to 0.
-    /// Overwriting pages that have already been written to may lead to unexpected behavior.
-    /// It is recommended to erase the sector before writing to it.
+    /// The register is read back afterwards, returning [Error::ReadbackFail] if the writable bits don't match,
+    /// e.g. because the status registers are protected.
     ///
-    /// # Arguments
-    /// * `address` - Address where the first byte of the buf will be written.
-    /// * `buf` - Slice of bytes that will be written.
-    pub async fn write(&mut self, mut address: u32, buf: &[u8]) -> Result<(), Error<S, P>> {
-        if address + buf.len() as u32 > CAPACITY {
-            return Err(Error::OutOfBounds);
-        }
+    /// This writes the status registers non-volatile, which wears the chip and keeps it busy for up to 15ms.
+    pub async fn write_status_register_1(&mut self, sr1: u8) -> Result<(), Error<S, P>> {
+        self.begin_operation().await?;
+        let sr2 = self.read_status_register_2().await?;
 
-        // Wait for any ongoing operations to complete
-        while self.busy().await? {}
+        self.write_status_register_bytes(&[Command::WriteStatusRegister1 as u8, sr1, sr2])
+            .await?;
 
-        // Write first chunk, taking into account that given address might
-        // point to a location that is not on a page boundary,
-        let chunk_len = core::cmp::min((PAGE_SIZE - (address & 0x000000FF)) as usize, buf.len());
-        self.write_page(address, &buf[..chunk_len]).await?;
+        self.check_status_register(Command::ReadStatusRegister1, sr1, SR1_WRITABLE)
+            .await
+    }
 
-        for chunk in buf[chunk_len..].chunks(PAGE_SIZE as usize) {
-            self.write_page(address, chunk).await?;
-            address += PAGE_SIZE;
-        }
+    /// Writes status register 2, which holds the quad enable, complement protect and lock bits.
+    ///
+    /// The register is read back afterwards, returning [Error::ReadbackFail] if the writable bits don't match,
+    /// e.g. because the status registers are protected. Note that the security register lock bits are one-time
+    /// programmable: once set, they can't be cleared again.
+    ///
+    /// This writes the status register non-volatile, which wears the chip and keeps it busy for up to 15ms.
+    pub async fn write_status_register_2(&mut self, sr2: u8) -> Result<(), Error<S, P>> {
+        self.write_status_register(Command::WriteStatusRegister2, sr2)
+            .await?;
 
-        Ok(())
+        self.check_status_register(Command::ReadStatusRegister2, sr2, SR2_WRITABLE)
+            .await
     }
 
-    /// Executes a page write operation on the flash chip.
+    /// Writes status register 3, which holds the hold/reset function, the output driver strength,
+    /// the write protect selection and the power-up address mode.
     ///
-    /// This function assumes that there are no ongoing operations on the chip, otherwise
-    /// the write operation will be silently ignored.
-    async fn write_page(&mut self, address: u32, buf: &[u8]) -> Result<(), Error<S, P>> {
-        // We don't support wrapping writes. They're scary
-        if (address & 0x000000FF) + buf.len() as u32 > PAGE_SIZE {
-            return Err(Error::OutOfBounds);
-        }
+    /// The register is read back afterwards, returning [Error::ReadbackFail] if the writable bits don't match,
+    /// e.g. because the status registers are protected.
+    ///
+    /// This writes the status register non-volatile, which wears the chip and keeps it busy for up to 15ms.
+    pub async fn write_status_register_3(&mut self, sr3: u8) -> Result<(), Error<S, P>> {
+        self.write_status_register(Command::WriteStatusRegister3, sr3)
+            .await?;
 
-        self.enable_write().await?;
+        self.check_status_register(Command::ReadStatusRegister3, sr3, SR3_WRITABLE)
+            .await
+    }
 
+    /// Sends a status register write command frame volatile: the value only lasts until the next power cycle or
+    /// reset, after which the non-volatile value applies again. Unlike the non-volatile writes, this doesn't wear
+    /// the chip and takes effect immediately.
+    ///
+    /// The frame is preceded by Write Enable for Volatile Status Register instead of the regular Write Enable.
+    async fn write_status_register_bytes_volatile(
+        &mut self,
+        frame: &[u8],
+    ) -> Result<(), Error<S, P>> {
+        self.begin_operation().await?;
+        self.wait_ready().await?;
         self.spi
-            .transaction(&mut [
-                Operation::Write(&command_and_address(
-                    Command::PageProgramWith4ByteAddress as u8,
-                    address,
-                )),
-                Operation::Write(buf),
-            ])
+            .write(&[Command::WriteEnableForVolatileStatusRegister as u8])
             .await
             .map_err(Error::SpiError)?;
 
-        // typical 0.7ms, max 3ms
-        while self.busy().await? {}
-
-        if cfg!(feature = "readback-check") {
-            self.readback_check(address, buf).await?;
-        }
-
-        Ok(())
+        self.spi.write(frame).await.map_err(Error::SpiError)
     }
 
-    /// Checks if the data at the provided address matches the provided slice.
-    async fn readback_check(&mut self, mut address: u32, data: &[u8]) -> Result<(), Error<S, P>> {
-        const CHUNK_SIZE: usize = 64;
+    /// Writes status register 1 volatile, see [W25q256jv::write_status_register_1] for the non-volatile write.
+    ///
+    /// The value only lasts until the next power cycle or reset. Status register 2 is read and written back
+    /// along with it, like the non-volatile write does.
+    ///
+    /// The register is read back afterwards, returning [Error::ReadbackFail] if the writable bits don't match,
+    /// e.g. because the status registers are protected.
+    pub async fn write_status_register_1_volatile(&mut self, sr1: u8) -> Result<(), Error<S, P>> {
+        self.begin_operation().await?;
+        let sr2 = self.read_status_register_2().await?;
 
-        let mut buf = [0; CHUNK_SIZE];
+        self.write_status_register_bytes_volatile(&[Command::WriteStatusRegister1 as u8, sr1, sr2])
+            .await?;
 
-        for chunk in data.chunks(CHUNK_SIZE) {
-            let buf = &mut buf[..chunk.len()];
-            self.read(address, buf).await?;
-            address += CHUNK_SIZE as u32;
+        self.check_status_register(Command::ReadStatusRegister1, sr1, SR1_WRITABLE)
+            .await
+    }
 
-            if buf != chunk {
-                return Err(Error::ReadbackFail);
-            }
-        }
+    /// Writes status register 2 volatile, see [W25q256jv::write_status_register_2] for the non-volatile write.
+    ///
+    /// The value only lasts until the next power cycle or reset, e.g. for setting the Quad Enable bit for the
+    /// current power cycle only.
+    ///
+    /// The register is read back afterwards, returning [Error::ReadbackFail] if the writable bits don't match,
+    /// e.g. because the status registers are protected.
+    pub async fn write_status_register_2_volatile(&mut self, sr2: u8) -> Result<(), Error<S, P>> {
+        self.write_status_register_bytes_volatile(&[Command::WriteStatusRegister2 as u8, sr2])
+            .await?;
 
-        Ok(())
+        self.check_status_register(Command::ReadStatusRegister2, sr2, SR2_WRITABLE)
+            .await
     }
 
-    /// Erases a range of sectors. The range is expressed in bytes. These bytes need to be a multiple of SECTOR_SIZE.
-    /// If the range starts at SECTOR_SIZE * 3 then the erase starts at the fourth sector.
-    /// All sectors are erased in the range [start_sector..end_sector].
-    /// The start address may not be a higher value than the end address.
+    /// Writes status register 3 volatile, see [W25q256jv::write_status_register_3] for the non-volatile write.
     ///
-    /// # Arguments
-    /// * `start_address` - Address of the first byte of the start of the range of sectors that need to be erased.
-    /// * `end_address` - Address of the first byte of the end of the range of sectors that need to be erased.
-    pub async fn erase_range(
+    /// The value only lasts until the next power cycle or reset.
+    ///
+    /// The register is read back afterwards, returning [Error::ReadbackFail] if the writable bits don't match,
+    /// e.g. because the status registers are protected.
+    pub async fn write_status_register_3_volatile(&mut self, sr3: u8) -> Result<(), Error<S, P>> {
+        self.write_status_register_bytes_volatile(&[Command::WriteStatusRegister3 as u8, sr3])
+            .await?;
+
+        self.check_status_register(Command::ReadStatusRegister3, sr3, SR3_WRITABLE)
+            .await
+    }
+
+    /// Reads a status register back and checks that the bits in `mask` have the expected values.
+    async fn check_status_register(
         &mut self,
-        start_address: u32,
-        end_address: u32,
+        command: Command,
+        expected: u8,
+        mask: u8,
     ) -> Result<(), Error<S, P>> {
-        if start_address % (SECTOR_SIZE) != 0 {
-            return Err(Error::NotAligned);
+        if (self.read_register(command).await? ^ expected) & mask != 0 {
+            return Err(Error::ReadbackFail);
         }
 
-        if end_address % (SECTOR_SIZE) != 0 {
-            return Err(Error::NotAligned);
-        }
+        Ok(())
+    }
 
-        if start_address > end_address {
-            return Err(Error::OutOfBounds);
+    /// Reads whether the /HOLD or /RESET pin (IO3) functions as a hold or as a reset pin.
+    pub async fn hold_reset_mode(&mut self) -> Result<HoldResetMode, Error<S, P>> {
+        if self.read_status_register_3().await? & 0x80 != 0 {
+            Ok(HoldResetMode::Reset)
+        } else {
+            Ok(HoldResetMode::Hold)
         }
+    }
 
-        let start_sector = start_address / SECTOR_SIZE;
-        let end_sector = end_address / SECTOR_SIZE;
+    /// Configures whether the /HOLD or /RESET pin (IO3) functions as a hold or as a reset pin.
+    ///
+    /// In reset mode, driving the HOLD pin of this driver low resets the chip, aborting any ongoing operation.
+    /// When quad mode is enabled (the QE bit in status register 2), IO3 is used for data and neither function is available.
+    ///
+    /// This writes status register 3 non-volatile, which wears the chip.
+    pub async fn set_hold_reset_mode(&mut self, mode: HoldResetMode) -> Result<(), Error<S, P>> {
+        let sr3 = self.read_status_register_3().await?;
+        let sr3 = match mode {
+            HoldResetMode::Hold => sr3 & !0x80,
+            HoldResetMode::Reset => sr3 | 0x80,
+        };
 
-        for sector in start_sector..end_sector {
-            self.erase_sector(sector).await.unwrap();
-        }
+        self.write_status_register(Command::WriteStatusRegister3, sr3)
+            .await
+    }
 
-        Ok(())
+    /// Locks the status registers against writes by setting the Status Register Protect (SRP) bit and asserting the WP pin.
+    ///
+    /// While SRP is set and WP is low, the chip ignores all status register writes, so the block protection
+    /// configuration can't be changed by software. It can only be undone by driving WP high again, see
+    /// [W25q256jv::hardware_unprotect_status_register]. The Status Register Lock (SRL) bit must be clear for this to apply.
+    ///
+    /// This writes status register 1 non-volatile, which wears the chip.
+    pub async fn hardware_protect_status_register(&mut self) -> Result<(), Error<S, P>> {
+        let sr1 = self.read_status_register().await?;
+        self.write_status_register_1(sr1 | 0x80).await?;
+
+        self.set_wp(PinState::Low)
     }
 
-    /// Erases a single sector of flash memory with the size of SECTOR_SIZE.
+    /// Unlocks the status registers locked with [W25q256jv::hardware_protect_status_register] by deasserting
+    /// the WP pin and clearing the Status Register Protect (SRP) bit.
     ///
-    /// # Arguments
-    /// * `index` - the index of the sector that needs to be erased. The address of the first byte of the sector is the provided index * SECTOR_SIZE.
-    pub async fn erase_sector(&mut self, index: u32) -> Result<(), Error<S, P>> {
-        if index >= N_SECTORS {
-            return Err(Error::OutOfBounds);
-        }
+    /// This writes status register 1 non-volatile, which wears the chip.
+    pub async fn hardware_unprotect_status_register(&mut self) -> Result<(), Error<S, P>> {
+        self.set_wp(PinState::High)?;
 
-        // in case the chip is still busy from previous operation
-        while self.busy().await? {}
+        let sr1 = self.read_status_register().await?;
+        self.write_status_register_1(sr1 & !0x80).await
+    }
 
-        self.enable_write().await?;
-        let address = index * SECTOR_SIZE;
+    /// Reads the JEDEC ID of the flash chip: the manufacturer ID, memory type and capacity.
+    async fn read_jedec_id_bytes(&mut self) -> Result<[u8; 3], Error<S, P>> {
+        let mut buf = [0; 4];
+        buf[0] = Command::ReadJedecId as u8;
 
         self.spi
-            .write(&command_and_address(
-                Command::SectorErase4KBWith4ByteAddress as u8,
-                address,
-            ))
+            .transfer_in_place(&mut buf)
             .await
             .map_err(Error::SpiError)?;
 
-        // typical 50ms, max 400ms
-        while self.busy().await? {}
+        Ok([buf[1], buf[2], buf[3]])
+    }
 
-        if cfg!(feature = "readback-check") {
-            for offset in (0..SECTOR_SIZE).step_by(64) {
-                self.readback_check(address + offset, &[0xFF; 64]).await?;
-            }
-        }
+    /// Reads the JEDEC ID of the flash chip, e.g. to check during board bring-up that the expected chip is populated.
+    /// A W25Q256JV reports [JEDEC_ID].
+    pub async fn read_jedec_id(&mut self) -> Result<JedecId, Error<S, P>> {
+        Ok(self.read_jedec_id_bytes().await?.into())
+    }
 
-        Ok(())
+    /// Reads the 64-bit unique ID that is factory-programmed into every chip, e.g. for use as a device serial number.
+    pub async fn read_unique_id(&mut self) -> Result<[u8; 8], Error<S, P>> {
+        // The command is followed by 4 dummy bytes, or by 5 in 4-byte address mode
+        let dummy_len = if self.in_4_byte_address_mode().await? {
+            5
+        } else {
+            4
+        };
+        self.command = [Command::ReadUniqueId as u8, 0, 0, 0, 0, 0];
+        let mut id = [0; 8];
+
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&self.command[..1 + dummy_len]),
+                Operation::Read(&mut id),
+            ])
+            .await
+            .map_err(Error::SpiError)?;
+
+        Ok(id)
     }
 
-    /// Erases a single block of flash memory with the size of BLOCK_32K_SIZE.
+    /// Reads from the Serial Flash Discoverable Parameters (SFDP) table of the chip.
     ///
     /// # Arguments
-    /// * `index` - the index of the block that needs to be erased. The address of the first byte of the block is the provided index * BLOCK_32K_SIZE.
-    pub async fn erase_block_32k(&mut self, index: u32) -> Result<(), Error<S, P>> {
-        if index >= N_BLOCKS_32K {
+    /// * `address` - Address within the SFDP table where the first byte of the buf will be read.
+    /// * `buf` - Slice that is going to be filled with the read bytes.
+    pub async fn read_sfdp(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error<S, P>> {
+        if address > 0xFFFFFF {
             return Err(Error::OutOfBounds);
         }
 
-        self.enable_write().await?;
-
-        // this command requires 4-byte address mode, so we enter it here.
-        self.enter_4_byte_address_mode().await?;
-
-        let address = index * BLOCK_32K_SIZE;
+        // The SFDP table is always addressed with a 3-byte address, followed by a dummy byte
+        command_and_address(&mut self.command, Command::ReadSfdp as u8, address << 8);
 
         self.spi
-            .write(&command_and_address(Command::BlockErase32KB as u8, address))
+            .transaction(&mut [Operation::Write(&self.command[..5]), Operation::Read(buf)])
             .await
             .map_err(Error::SpiError)?;
 
-        // typical 120ms, max 1600ms
-        while self.busy().await? {}
+        Ok(())
+    }
 
-        // we don't need to exit 4-byte address mode as no command in our driver
-        // requires 3-byte address mode.
+    /// Reads the JEDEC Basic Flash Parameter Table from the SFDP table into the provided buffer.
+    /// Returns the number of bytes read, which is limited by the length of the table and of the buffer.
+    async fn read_sfdp_basic_parameter_table(
+        &mut self,
+        buf: &mut [u8],
+    ) -> Result<usize, Error<S, P>> {
+        let mut header = [0; 16];
+        self.read_sfdp(0, &mut header).await?;
 
-        if cfg!(feature = "readback-check") {
-            for offset in (0..BLOCK_32K_SIZE).step_by(64) {
-                self.readback_check(address + offset, &[0xFF; 64]).await?;
-            }
+        if header[..4] != *b"SFDP" {
+            return Err(Error::InvalidSfdp);
         }
 
-        Ok(())
-    }
+        // The first parameter header always describes the basic flash parameter table
+        let parameter_header = &header[8..16];
+        if parameter_header[0] != 0x00 || parameter_header[7] != 0xFF {
+            return Err(Error::InvalidSfdp);
+        }
 
-    /// Erases a single block of flash memory with the size of BLOCK_64K_SIZE.
+        let len = core::cmp::min(parameter_header[3] as usize * 4, buf.len());
+        let pointer = u32::from_le_bytes([
+            parameter_header[4],
+            parameter_header[5],
+            parameter_header[6],
+            0,
+        ]);
+
+        self.read_sfdp(pointer, &mut buf[..len]).await?;
+
+        Ok(len)
+    }
+
+    /// Reads the Winbond vendor-specific parameter table from the SFDP table into the provided buffer.
+    /// The table is located through the parameter headers following the SFDP header.
     ///
-    /// Waits for the chip to complete its current operation before starting the erase operation.
+    /// Returns the number of bytes read, which is limited by the length of the table and of the buffer,
+    /// or `None` if the chip doesn't declare a vendor table.
+    pub async fn read_vendor_sfdp_table(
+        &mut self,
+        buf: &mut [u8],
+    ) -> Result<Option<usize>, Error<S, P>> {
+        let mut header = [0; 8];
+        self.read_sfdp(0, &mut header).await?;
+
+        if header[..4] != *b"SFDP" {
+            return Err(Error::InvalidSfdp);
+        }
+
+        // The number of parameter headers is stored zero-based
+        let n_parameter_headers = header[6] as u32 + 1;
+
+        for i in 0..n_parameter_headers {
+            let mut parameter_header = [0; 8];
+            self.read_sfdp(8 + i * 8, &mut parameter_header).await?;
+
+            // Vendor tables are identified by the JEDEC manufacturer ID of Winbond
+            if parameter_header[0] != JEDEC_ID[0] {
+                continue;
+            }
+
+            let len = core::cmp::min(parameter_header[3] as usize * 4, buf.len());
+            let pointer = u32::from_le_bytes([
+                parameter_header[4],
+                parameter_header[5],
+                parameter_header[6],
+                0,
+            ]);
+
+            self.read_sfdp(pointer, &mut buf[..len]).await?;
+
+            return Ok(Some(len));
+        }
+
+        Ok(None)
+    }
+
+    /// Derives the timing profile from the typical times the chip declares in its SFDP table and uses it from then on.
+    /// Times the table doesn't declare keep their datasheet values.
+    ///
+    /// The profile paces the busy polling during operations if a delay source is configured,
+    /// see [W25q256jv::with_inter_command_delay].
+    pub async fn load_timing_profile_from_sfdp(&mut self) -> Result<TimingProfile, Error<S, P>> {
+        let mut table = [0; 64];
+        let len = self.read_sfdp_basic_parameter_table(&mut table).await?;
+
+        self.timing = TimingProfile::from_basic_parameter_table(&table[..len]);
+
+        Ok(self.timing)
+    }
+
+    /// Brings the chip into a known state after power-on or after a reset of the microcontroller.
+    ///
+    /// The following steps are taken:
+    /// 1. Wait the power-up time (tVSL and tPUW) before the chip accepts commands.
+    /// 2. Optionally reset the continuous read mode a previous firmware may have left the chip in.
+    /// 3. Wait for any operation a previous firmware may have started to complete.
+    /// 4. Check the JEDEC ID, returning [Error::UnexpectedId] if the chip isn't a W25Q256JV.
+    /// 5. Clear the write-enable flag, in case it was left set.
+    /// 6. Optionally clear the Quad Enable bit.
+    /// 7. Optionally enter 4-byte address mode.
     ///
     /// # Arguments
-    /// * `index` - the index of the block that needs to be erased. The address of the first byte of the block is the provided index * BLOCK_64K_SIZE.
-    pub async fn erase_block_64k(&mut self, index: u32) -> Result<(), Error<S, P>> {
-        if index >= N_BLOCKS_64K {
-            return Err(Error::OutOfBounds);
+    /// * `delay` - Delay source used to wait the power-up time.
+    /// * `config` - Which optional steps to take.
+    pub async fn init(
+        &mut self,
+        delay: &mut impl DelayNs,
+        config: InitConfig,
+    ) -> Result<(), Error<S, P>> {
+        // tPUW, max 10ms. This also covers tVSL.
+        delay.delay_ms(10).await;
+
+        if config.exit_continuous_read {
+            // Clocking in ones as mode bits makes the chip leave continuous read mode.
+            // Two bytes cover the address phase in 4-byte address mode.
+            self.spi.write(&[0xFF; 2]).await.map_err(Error::SpiError)?;
         }
 
-        while self.busy().await? {} // in case the chip is still busy from previous operation
+        self.wait_ready().await?;
 
-        self.enable_write().await?;
+        let id = self.read_jedec_id().await?;
+        if !id.is_w25q256jv() {
+            return Err(Error::UnexpectedId { read: id.into() });
+        }
 
-        let address = index * BLOCK_64K_SIZE;
+        self.disable_write().await?;
+
+        if config.clear_quad_enable {
+            self.clear_quad_enable().await?;
+        }
+
+        if config.enter_4_byte_address_mode {
+            self.enter_4_byte_address_mode().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Clears the Quad Enable bit if it is set, so the WP and HOLD pins function as such again.
+    /// The status register is only written if needed, as the write is non-volatile and wears the chip.
+    async fn clear_quad_enable(&mut self) -> Result<(), Error<S, P>> {
+        let sr2 = self.read_status_register_2().await?;
+        if sr2 & 0x02 != 0 {
+            self.write_status_register(Command::WriteStatusRegister2, sr2 & !0x02)
+                .await?;
+        }
+
+        Ok(())
+    }
 
+    /// Returns true if the Quad Enable bit is set, which makes the WP and HOLD pins act as data lines IO2 and IO3.
+    /// The bit is non-volatile, so it may have been left set by a previous firmware.
+    pub async fn quad_enabled(&mut self) -> Result<bool, Error<S, P>> {
+        Ok((self.read_status_register_2().await? & 0x02) != 0)
+    }
+
+    /// Returns true if the Complement Protect (CMP) bit in status register 2 is set,
+    /// which inverts the region selected by the block protection bits, see [BlockProtection].
+    pub async fn complement_protect(&mut self) -> Result<bool, Error<S, P>> {
+        Ok((self.read_status_register_2().await? & 0x40) != 0)
+    }
+
+    /// Sets or clears the Complement Protect (CMP) bit in status register 2, leaving its other bits as they are.
+    /// Combined with the block protection bits, this allows protecting e.g. all but the top of the array.
+    ///
+    /// This writes status register 2 non-volatile, which wears the chip.
+    pub async fn set_complement_protect(&mut self, enabled: bool) -> Result<(), Error<S, P>> {
+        let sr2 = self.read_status_register_2().await?;
+        let sr2 = if enabled { sr2 | 0x40 } else { sr2 & !0x40 };
+
+        self.write_status_register(Command::WriteStatusRegister2, sr2)
+            .await
+    }
+
+    /// Returns true while an erase or program operation is suspended.
+    pub async fn is_suspended(&mut self) -> Result<bool, Error<S, P>> {
+        Ok((self.read_status_register_2().await? & 0x80) != 0)
+    }
+
+    /// Returns whether each of the three security registers is permanently locked against programming,
+    /// i.e. the lock bits LB1 to LB3 in status register 2. Index 0 corresponds to security register 1.
+    pub async fn security_lock_bits(&mut self) -> Result<[bool; 3], Error<S, P>> {
+        let sr2 = self.read_status_register_2().await?;
+
+        Ok([sr2 & 0x08 != 0, sr2 & 0x10 != 0, sr2 & 0x20 != 0])
+    }
+
+    /// Suspends the ongoing erase or program operation and waits until the chip accepts other commands.
+    ///
+    /// This lets a more urgent read go ahead of a long erase. Per datasheet, the command is ignored if no operation
+    /// is ongoing or if the operation is already suspended, i.e. a suspended operation can't be suspended again.
+    /// While suspended, reads must avoid the sector or block being erased, and the page being programmed, as their
+    /// contents are undefined. Call [W25q256jv::resume] when done, see also [W25q256jv::is_suspended].
+    pub async fn suspend(&mut self) -> Result<(), Error<S, P>> {
         self.spi
-            .write(&command_and_address(
-                Command::BlockErase64KBWith4ByteAddress as u8,
-                address,
-            ))
+            .write(&[Command::EraseProgramSuspend as u8])
             .await
             .map_err(Error::SpiError)?;
 
-        // typical 150ms, max 1600ms
+        // max 20us
         while self.busy().await? {}
 
-        if cfg!(feature = "readback-check") {
-            for offset in (0..BLOCK_64K_SIZE).step_by(64) {
-                self.readback_check(address + offset, &[0xFF; 64]).await?;
-            }
+        Ok(())
+    }
+
+    /// Resumes a suspended erase or program operation. Does not wait for the operation to complete.
+    ///
+    /// The command is ignored if no operation is suspended.
+    pub async fn resume(&mut self) -> Result<(), Error<S, P>> {
+        self.resume_pending = false;
+        self.spi
+            .write(&[Command::EraseProgramResume as u8])
+            .await
+            .map_err(Error::SpiError)?;
+
+        Ok(())
+    }
+
+    /// Suspends the ongoing erase operation, so the chip can be read in the meantime.
+    ///
+    /// The erase functions of this driver wait until the erase is complete. An erase keeps running on the chip
+    /// when its future is dropped though, e.g. when it loses a select against a more urgent event.
+    /// This function can then suspend it.
+    ///
+    /// The returned guard gives access to the driver while the erase is suspended. Call [suspend::EraseGuard::resume]
+    /// when done. As there is no async drop, dropping the guard can't resume the erase immediately. Instead the erase is
+    /// resumed by the next operation that waits for the chip to be ready.
+    pub async fn erase_suspend(
+        &mut self,
+    ) -> Result<suspend::EraseGuard<'_, SPI, HOLD, WP, DELAY>, Error<S, P>> {
+        self.suspend().await?;
+
+        Ok(suspend::EraseGuard::new(self))
+    }
+
+    /// The flash chip will enter into 4-byte address mode. The factory default is 3-byte
+    /// address mode. Note that the W25Q256JV supports dedicated 4-byte address mode commands,
+    /// which take 4-byte addresses regardless of the address mode.
+    ///
+    /// The address mode is read back afterwards. Returns [Error::AddressModeFail] if the chip didn't switch.
+    pub async fn enter_4_byte_address_mode(&mut self) -> Result<(), Error<S, P>> {
+        self.spi
+            .write(&[Command::Enter4ByteAddressMode as u8])
+            .await
+            .map_err(Error::SpiError)?;
+
+        if !self.in_4_byte_address_mode().await? {
+            return Err(Error::AddressModeFail);
         }
 
         Ok(())
     }
 
-    /// Erases all sectors on the flash chip. This is a very expensive operation.
+    /// Returns true if the chip is in 4-byte address mode, as reported by the ADS bit in status register 3.
+    pub async fn in_4_byte_address_mode(&mut self) -> Result<bool, Error<S, P>> {
+        Ok((self.read_status_register_3().await? & 0x01) != 0)
+    }
+
+    /// The flash chip will exit 4-byte address mode. The factory default is 3-byte
+    /// address mode. Note that the W25Q256JV supports dedicated 4-byte address mode commands,
+    /// which take 4-byte addresses regardless of the address mode.
+    async fn exit_4_byte_address_mode(&mut self) -> Result<(), Error<S, P>> {
+        self.spi
+            .write(&[Command::Exit4ByteAddressMode as u8])
+            .await
+            .map_err(Error::SpiError)?;
+
+        Ok(())
+    }
+
+    /// Resets the chip without respect to ongoing operations.
     ///
-    /// Waits for the chip to complete its current operation before starting the erase operation.
-    pub async fn erase_chip(&mut self) -> Result<(), Error<S, P>> {
-        while self.busy().await? {} // in case the chip is still busy from a previous operation
+    /// # Safety
+    /// Data corruption may happen if there is an ongoing or suspended internal Erase or Program operation.
+    pub async unsafe fn reset(&mut self) -> Result<(), Error<S, P>> {
+        // The reset is only accepted directly after Enable Reset
+        self.spi
+            .write(&[Command::EnableReset as u8])
+            .await
+            .map_err(Error::SpiError)?;
+        self.spi
+            .write(&[Command::ResetDevice as u8])
+            .await
+            .map_err(Error::SpiError)?;
+        Ok(())
+    }
 
-        self.enable_write().await?;
+    /// Resets the chip once it is safe to do so, and waits until it accepts commands again.
+    ///
+    /// An ongoing erase or program operation is completed first, resuming it if it is suspended, so no data is corrupted.
+    /// The reset returns the volatile settings to their defaults, e.g. the address mode to the one selected by the ADP bit.
+    ///
+    /// # Arguments
+    /// * `delay` - Delay source used to wait the reset time (tRST).
+    pub async fn reset_recover(&mut self, delay: &mut impl DelayNs) -> Result<(), Error<S, P>> {
+        self.wait_ready().await?;
+        if self.is_suspended().await? {
+            self.resume().await?;
+            self.wait_ready().await?;
+        }
+
+        // SAFETY: there is no ongoing or suspended operation anymore
+        unsafe { self.reset().await? };
+        self.in_operation = false;
+
+        // tRST, max 30us
+        delay.delay_us(30).await;
+
+        Ok(())
+    }
+
+    /// Returns the chip to single-line SPI after an interrupted quad operation, e.g. a Fast Read Quad I/O left in
+    /// continuous read mode by a bootloader, and confirms it by reading the JEDEC ID over single-line SPI.
+    ///
+    /// Ones are clocked in as mode bits to leave continuous read mode, then the chip is reset with
+    /// [W25q256jv::reset_recover]. Returns [Error::UnexpectedId] if the chip still doesn't report [JEDEC_ID].
+    ///
+    /// # Arguments
+    /// * `delay` - Delay source used to wait the reset time (tRST).
+    pub async fn recover_from_quad(&mut self, delay: &mut impl DelayNs) -> Result<(), Error<S, P>> {
+        // Two bytes cover the address phase in 4-byte address mode
+        self.spi.write(&[0xFF; 2]).await.map_err(Error::SpiError)?;
+
+        self.reset_recover(delay).await?;
+
+        let id = self.read_jedec_id().await?;
+        if !id.is_w25q256jv() {
+            return Err(Error::UnexpectedId { read: id.into() });
+        }
+
+        Ok(())
+    }
+
+    /// Waits the configured inter-command delay, if any.
+    async fn inter_command_delay(&mut self) {
+        if self.inter_command_delay_ns > 0 {
+            self.delay.delay_ns(self.inter_command_delay_ns).await;
+        }
+    }
+
+    /// Adds to the programmed bytes statistic, if the `stats` feature is enabled.
+    fn record_program(&mut self, _len: u32) {
+        #[cfg(feature = "stats")]
+        {
+            self.stats.total_bytes_programmed += _len as u64;
+        }
+    }
+
+    /// Adds to the erased bytes statistic, if the `stats` feature is enabled.
+    fn record_erase(&mut self, _len: u32) {
+        #[cfg(feature = "stats")]
+        {
+            self.stats.total_bytes_erased += _len as u64;
+        }
+    }
+
+    /// Reads a chunk of bytes from the flash chip.
+    /// The number of bytes read is equal to the length of the buf slice.
+    /// The first byte is read from the provided address. This address is then incremented for each following byte.
+    ///
+    /// The command and the data are sent as two operations of one SPI transaction. This requires the
+    /// `SpiDevice` to keep chip select asserted for the whole transaction, as the embedded-hal contract demands.
+    /// If an implementation toggles chip select between operations, the chip aborts the read and returns garbage.
+    /// Use [W25q256jv::read_single_transfer] for such implementations.
+    ///
+    /// The read may end exactly at the end of the chip, i.e. `address + buf.len() == CAPACITY` is allowed,
+    /// reading up to and including the last byte. Any read extending further returns [Error::OutOfBounds].
+    ///
+    /// # Arguments
+    /// * `address` - Address where the first byte of the buf will be read.
+    /// * `buf` - Slice that is going to be filled with the read bytes.
+    pub async fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error<S, P>> {
+        check_slice(address, buf.len())?;
 
         self.spi
-            .write(&[Command::ChipErase as u8])
+            .transaction(&mut [
+                Operation::Write(command_and_address(
+                    &mut self.command,
+                    Command::ReadDataWith4ByteAddress as u8,
+                    address,
+                )),
+                Operation::Read(buf),
+            ])
             .await
             .map_err(Error::SpiError)?;
 
-        // typical 80s, max 400s
-        while self.busy().await? {}
+        Ok(())
+    }
 
-        if cfg!(feature = "readback-check") {
-            for address in (0..CAPACITY).step_by(64) {
-                self.readback_check(address, &[0xFF; 64]).await?;
-            }
+    /// Reads a chunk of bytes from the flash chip like [W25q256jv::read], but with the Fast Read command.
+    /// The command has a dummy byte after the address, which allows for higher clock frequencies.
+    ///
+    /// # Arguments
+    /// * `address` - Address where the first byte of the buf will be read.
+    /// * `buf` - Slice that is going to be filled with the read bytes.
+    pub async fn fast_read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error<S, P>> {
+        check_slice(address, buf.len())?;
+
+        command_and_address(
+            &mut self.command,
+            Command::FastReadWith4ByteAddress as u8,
+            address,
+        );
+        self.command[5] = 0; // dummy byte
+
+        self.spi
+            .transaction(&mut [Operation::Write(&self.command), Operation::Read(buf)])
+            .await
+            .map_err(Error::SpiError)?;
+
+        Ok(())
+    }
+
+    /// Reads a chunk of bytes from the flash chip using a single full-duplex transfer.
+    ///
+    /// Unlike [W25q256jv::read], the command and the data share one buffer, so chip select can't be
+    /// toggled in between, even by `SpiDevice` implementations that do so between operations.
+    /// The first READ_COMMAND_LEN bytes of the frame are overwritten with the command, the rest is filled with the read bytes.
+    ///
+    /// # Arguments
+    /// * `address` - Address where the first byte of data will be read.
+    /// * `frame` - Slice of READ_COMMAND_LEN bytes plus the number of bytes to read.
+    ///
+    /// Returns the part of the frame that holds the read bytes.
+    pub async fn read_single_transfer<'b>(
+        &mut self,
+        address: u32,
+        frame: &'b mut [u8],
+    ) -> Result<&'b mut [u8], Error<S, P>> {
+        if frame.len() < READ_COMMAND_LEN {
+            return Err(Error::OutOfBounds);
         }
 
+        check_slice(address, frame.len() - READ_COMMAND_LEN)?;
+
+        frame[..READ_COMMAND_LEN].copy_from_slice(command_and_address(
+            &mut self.command,
+            Command::ReadDataWith4ByteAddress as u8,
+            address,
+        ));
+
+        self.spi
+            .transfer_in_place(frame)
+            .await
+            .map_err(Error::SpiError)?;
+
+        Ok(&mut frame[READ_COMMAND_LEN..])
+    }
+
+    /// Reads a chunk of bytes from the flash chip and feeds them into a digest, avoiding a second
+    /// pass over the data when its checksum is needed as well.
+    ///
+    /// # Arguments
+    /// * `address` - Address where the first byte of the buf will be read.
+    /// * `buf` - Slice that is going to be filled with the read bytes.
+    /// * `digest` - Digest that is updated with the read bytes.
+    pub async fn read_hashed(
+        &mut self,
+        address: u32,
+        buf: &mut [u8],
+        digest: &mut impl Digest,
+    ) -> Result<(), Error<S, P>> {
+        self.read(address, buf).await?;
+        digest.update(buf);
+
         Ok(())
     }
+
+    /// Writes a chunk of bytes to the flash chip.
+    /// The first byte is written to the provided address. This address is then incremented for each following byte.
+    ///
+    /// This function will wait for any ongoing operations to complete before starting the write operation,
+    /// to prevent data corruption. If a write or erase of this driver whose future was dropped is still running,
+    /// [Error::Busy] is returned instead.
+    ///
+    /// As this is a NOR-flash chip, the write operation will only change bits from 1 to 0.
+    /// Overwriting pages that have already been written to may lead to unexpected behavior.
+    /// It is recommended to erase the sector before writing to it.
+    ///
+    /// # Arguments
+    /// * `address` - Address where the first byte of the buf will be written.
+    /// * `buf` - Slice of bytes that will be written.
+    pub async fn write(&mut self, address: u32, buf: &[u8]) -> Result<(), Error<S, P>> {
+        self.write_counted(address, buf)
+            .await
+            .map(|_| ())
+            .map_err(|(_, error)| error)
+    }
+
+    /// Writes a chunk of bytes like [W25q256jv::write], keeping count of the bytes programmed.
+    /// This lets streaming callers resume after a failure instead of starting over.
+    ///
+    /// # Arguments
+    /// * `address` - Address where the first byte of the buf will be written.
+    /// * `buf` - Slice of bytes that will be written.
+    ///
+    /// Returns `buf.len()` on success. On failure, the error is returned together with the number of bytes that were
+    /// programmed before it, i.e. the offset in `buf` to resume from.
+    pub async fn write_counted(
+        &mut self,
+        address: u32,
+        buf: &[u8],
+    ) -> Result<usize, (usize, Error<S, P>)> {
+        if buf.len() > self.max_write_len {
+            return Err((0, Error::WriteTooLarge));
+        }
+
+        self.write_pages(address, buf).await
+    }
+
+    /// Writes a chunk of bytes page by page, see [W25q256jv::write_counted].
+    ///
+    /// Unlike the public writes, this doesn't apply the limit of [W25q256jv::set_max_write_len], so the driver's own
+    /// writes, e.g. of a whole sector in a read-modify-write, aren't cut short.
+    async fn write_pages(
+        &mut self,
+        mut address: u32,
+        buf: &[u8],
+    ) -> Result<usize, (usize, Error<S, P>)> {
+        check_slice(address, buf.len()).map_err(|error| (0, error))?;
+
+        self.begin_operation().await.map_err(|error| (0, error))?;
+        // The chip may still be busy with an operation this driver didn't start, e.g. before a reset of the MCU
+        self.wait_ready().await.map_err(|error| (0, error))?;
+
+        // Write first chunk, taking into account that given address might
+        // point to a location that is not on a page boundary,
+        let chunk_len = core::cmp::min((PAGE_SIZE - (address & 0x000000FF)) as usize, buf.len());
+        self.write_page(address, &buf[..chunk_len])
+            .await
+            .map_err(|error| (0, error))?;
+        let mut written = chunk_len;
+        // The following chunks start on page boundaries. This also carries into the high address byte,
+        // e.g. from the page at 0x00FFFF00 to the page at 0x01000000.
+        address += chunk_len as u32;
+
+        for chunk in buf[chunk_len..].chunks(PAGE_SIZE as usize) {
+            self.inter_command_delay().await;
+            self.write_page(address, chunk)
+                .await
+                .map_err(|error| (written, error))?;
+            written += chunk.len();
+            address += PAGE_SIZE;
+        }
+
+        Ok(written)
+    }
+
+    /// Executes a page write operation on the flash chip.
+    ///
+    /// This function assumes that there are no ongoing operations on the chip, otherwise
+    /// the write operation will be silently ignored.
+    async fn write_page(&mut self, address: u32, buf: &[u8]) -> Result<(), Error<S, P>> {
+        // We don't support wrapping writes. They're scary
+        if (address & 0x000000FF) + buf.len() as u32 > PAGE_SIZE {
+            return Err(Error::OutOfBounds);
+        }
+
+        self.begin_operation().await?;
+        self.enable_write().await?;
+
+        self.spi
+            .transaction(&mut [
+                Operation::Write(command_and_address(
+                    &mut self.command,
+                    Command::PageProgramWith4ByteAddress as u8,
+                    address,
+                )),
+                Operation::Write(buf),
+            ])
+            .await
+            .map_err(Error::SpiError)?;
+        self.in_operation = true;
+        self.record_program(buf.len() as u32);
+
+        // typical 0.7ms, max 3ms
+        self.wait_done(self.timing.page_program_us).await?;
+
+        if cfg!(feature = "verify-writes") {
+            self.readback_check(address, buf).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks if the data at the provided address matches the provided slice.
+    async fn readback_check(&mut self, mut address: u32, data: &[u8]) -> Result<(), Error<S, P>> {
+        const CHUNK_SIZE: usize = 64;
+
+        let mut buf = [0; CHUNK_SIZE];
+
+        for chunk in data.chunks(CHUNK_SIZE) {
+            let buf = &mut buf[..chunk.len()];
+            self.read(address, buf).await?;
+            address += CHUNK_SIZE as u32;
+
+            if buf != chunk {
+                return Err(Error::ReadbackFail);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks if every byte in the provided range equals the provided value.
+    async fn is_filled_with(
+        &mut self,
+        mut address: u32,
+        len: u32,
+        value: u8,
+    ) -> Result<bool, Error<S, P>> {
+        const CHUNK_SIZE: u32 = 64;
+
+        let mut buf = [0; CHUNK_SIZE as usize];
+        let end = address + len;
+
+        while address < end {
+            let buf = &mut buf[..core::cmp::min(CHUNK_SIZE, end - address) as usize];
+            self.read(address, buf).await?;
+            address += buf.len() as u32;
+
+            if buf.iter().any(|byte| *byte != value) {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Erases a range of sectors. The range is expressed in bytes. These bytes need to be a multiple of SECTOR_SIZE.
+    /// If the range starts at SECTOR_SIZE * 3 then the erase starts at the fourth sector.
+    /// All sectors are erased in the range [start_sector..end_sector].
+    /// The start address may not be a higher value than the end address.
+    ///
+    /// The range is validated the same way as embedded-storage's `check_erase` helper before anything is erased,
+    /// so the `NorFlash` implementation reports the error kinds generic stacks expect. Errors of the
+    /// individual sector erases are returned rather than panicking.
+    ///
+    /// # Arguments
+    /// * `start_address` - Address of the first byte of the start of the range of sectors that need to be erased.
+    /// * `end_address` - Address of the first byte of the end of the range of sectors that need to be erased.
+    pub async fn erase_range(
+        &mut self,
+        start_address: u32,
+        end_address: u32,
+    ) -> Result<(), Error<S, P>> {
+        check_erase(start_address, end_address, SECTOR_SIZE)?;
+
+        let start_sector = start_address / SECTOR_SIZE;
+        let end_sector = end_address / SECTOR_SIZE;
+
+        for sector in start_sector..end_sector {
+            if sector != start_sector {
+                self.inter_command_delay().await;
+            }
+            self.erase_sector(sector).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts erasing a single sector without waiting for the erase to complete.
+    /// Returns the address of the sector.
+    async fn start_erase_sector(&mut self, index: u32) -> Result<u32, Error<S, P>> {
+        if index >= N_SECTORS {
+            return Err(Error::OutOfBounds);
+        }
+
+        self.begin_operation().await?;
+        // The chip may still be busy with an operation this driver didn't start, e.g. before a reset of the MCU
+        self.wait_ready().await?;
+
+        self.enable_write().await?;
+        let address = index * SECTOR_SIZE;
+
+        self.spi
+            .write(command_and_address(
+                &mut self.command,
+                Command::SectorErase4KBWith4ByteAddress as u8,
+                address,
+            ))
+            .await
+            .map_err(Error::SpiError)?;
+        self.in_operation = true;
+        self.record_erase(SECTOR_SIZE);
+
+        Ok(address)
+    }
+
+    /// Erases a single sector like [W25q256jv::erase_sector] and measures how long the chip took to erase it.
+    /// Useful for tuning timeouts to the actual chip rather than the datasheet maxima.
+    ///
+    /// The erase is not verified, as that would not be part of the measured time.
+    ///
+    /// # Arguments
+    /// * `index` - the index of the sector that needs to be erased.
+    /// * `clock` - Returns the current time in ticks of any unit. Allowed to wrap around.
+    ///
+    /// Returns the number of ticks between issuing the erase and the chip no longer being busy.
+    #[cfg(feature = "diagnostics")]
+    pub async fn time_erase_sector(
+        &mut self,
+        index: u32,
+        clock: impl Fn() -> u32,
+    ) -> Result<u32, Error<S, P>> {
+        self.start_erase_sector(index).await?;
+        let start = clock();
+
+        self.wait_ready().await?;
+        self.in_operation = false;
+
+        Ok(clock().wrapping_sub(start))
+    }
+
+    /// Erases a single sector of flash memory with the size of SECTOR_SIZE.
+    ///
+    /// If the erase fails verification (with the `verify-erases` feature), it is retried as often as configured
+    /// with [W25q256jv::set_erase_retries].
+    ///
+    /// # Arguments
+    /// * `index` - the index of the sector that needs to be erased. The address of the first byte of the sector is the provided index * SECTOR_SIZE.
+    pub async fn erase_sector(&mut self, index: u32) -> Result<(), Error<S, P>> {
+        let mut retries = self.erase_retries;
+
+        loop {
+            match self.try_erase_sector(index).await {
+                Err(Error::ReadbackFail) if retries > 0 => retries -= 1,
+                result => return result,
+            }
+        }
+    }
+
+    /// Erases a single sector once, without retrying when the verification fails.
+    async fn try_erase_sector(&mut self, index: u32) -> Result<(), Error<S, P>> {
+        let address = self.start_erase_sector(index).await?;
+
+        // typical 50ms, max 400ms
+        self.wait_done(self.timing.sector_erase_us).await?;
+
+        if cfg!(feature = "verify-erases") {
+            for offset in (0..SECTOR_SIZE).step_by(64) {
+                self.readback_check(address + offset, &[0xFF; 64]).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Erases a single block of flash memory with the size of BLOCK_32K_SIZE.
+    ///
+    /// Waits for the chip to complete its current operation before starting the erase operation.
+    ///
+    /// # Arguments
+    /// * `index` - the index of the block that needs to be erased. The address of the first byte of the block is the provided index * BLOCK_32K_SIZE.
+    pub async fn erase_block_32k(&mut self, index: u32) -> Result<(), Error<S, P>> {
+        if index >= N_BLOCKS_32K {
+            return Err(Error::OutOfBounds);
+        }
+
+        self.begin_operation().await?;
+        // The chip may still be busy with an operation this driver didn't start, e.g. before a reset of the MCU
+        self.wait_ready().await?;
+
+        self.enable_write().await?;
+
+        let address = index * BLOCK_32K_SIZE;
+
+        self.spi
+            .write(command_and_address(
+                &mut self.command,
+                Command::BlockErase32KBWith4ByteAddress as u8,
+                address,
+            ))
+            .await
+            .map_err(Error::SpiError)?;
+        self.in_operation = true;
+        self.record_erase(BLOCK_32K_SIZE);
+
+        // typical 120ms, max 1600ms
+        self.wait_done(self.timing.block_32k_erase_us).await?;
+
+        if cfg!(feature = "verify-erases") {
+            for offset in (0..BLOCK_32K_SIZE).step_by(64) {
+                self.readback_check(address + offset, &[0xFF; 64]).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Erases a single block of flash memory with the size of BLOCK_64K_SIZE.
+    ///
+    /// Waits for the chip to complete its current operation before starting the erase operation.
+    ///
+    /// # Arguments
+    /// * `index` - the index of the block that needs to be erased. The address of the first byte of the block is the provided index * BLOCK_64K_SIZE.
+    pub async fn erase_block_64k(&mut self, index: u32) -> Result<(), Error<S, P>> {
+        if index >= N_BLOCKS_64K {
+            return Err(Error::OutOfBounds);
+        }
+
+        self.begin_operation().await?;
+        // The chip may still be busy with an operation this driver didn't start, e.g. before a reset of the MCU
+        self.wait_ready().await?;
+
+        self.enable_write().await?;
+
+        let address = index * BLOCK_64K_SIZE;
+
+        self.spi
+            .write(command_and_address(
+                &mut self.command,
+                Command::BlockErase64KBWith4ByteAddress as u8,
+                address,
+            ))
+            .await
+            .map_err(Error::SpiError)?;
+        self.in_operation = true;
+        self.record_erase(BLOCK_64K_SIZE);
+
+        // typical 150ms, max 1600ms
+        self.wait_done(self.timing.block_64k_erase_us).await?;
+
+        if cfg!(feature = "verify-erases") {
+            for offset in (0..BLOCK_64K_SIZE).step_by(64) {
+                self.readback_check(address + offset, &[0xFF; 64]).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Erases all sectors on the flash chip. This is a very expensive operation.
+    ///
+    /// Waits for the chip to complete its current operation before starting the erase operation.
+    pub async fn erase_chip(&mut self) -> Result<(), Error<S, P>> {
+        self.begin_operation().await?;
+        // The chip may still be busy with an operation this driver didn't start, e.g. before a reset of the MCU
+        self.wait_ready().await?;
+
+        self.enable_write().await?;
+
+        self.spi
+            .write(&[Command::ChipErase as u8])
+            .await
+            .map_err(Error::SpiError)?;
+        self.in_operation = true;
+        self.record_erase(CAPACITY);
+
+        // typical 80s, max 400s
+        self.wait_done(self.timing.chip_erase_us).await?;
+
+        if cfg!(feature = "verify-erases") && !self.is_chip_erased().await? {
+            return Err(Error::ReadbackFail);
+        }
+
+        Ok(())
+    }
+
+    /// Quickly checks whether the whole chip is erased, e.g. before provisioning.
+    ///
+    /// Reading back the whole chip takes far too long, so only the first and last page of every sample interval
+    /// are checked, see [W25q256jv::set_chip_erase_sample_interval]. This is a probabilistic check: data outside
+    /// of the sampled pages goes unnoticed.
+    ///
+    /// Returns false as soon as a sampled byte isn't 0xFF.
+    pub async fn is_chip_erased(&mut self) -> Result<bool, Error<S, P>> {
+        let interval = self.chip_erase_sample_interval;
+
+        for start in (0..CAPACITY).step_by(interval as usize) {
+            let last_page = core::cmp::min(start + interval, CAPACITY) - PAGE_SIZE;
+
+            if !self.is_filled_with(start, PAGE_SIZE, 0xFF).await? {
+                return Ok(false);
+            }
+            if last_page != start && !self.is_filled_with(last_page, PAGE_SIZE, 0xFF).await? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Screens the whole chip for sectors that can't be reliably erased and programmed.
+    /// Every sector is erased, checked to be blank, programmed with a test pattern and read back.
+    ///
+    /// **This destroys all data on the chip.** It takes a very long time, as every sector is erased and programmed.
+    ///
+    /// # Arguments
+    /// * `report` - Called with the index of every sector that failed verification.
+    pub async fn scan_for_errors(
+        &mut self,
+        report: &mut impl FnMut(u32),
+    ) -> Result<(), Error<S, P>> {
+        for sector in 0..N_SECTORS {
+            if !self.screen_sector(sector).await? {
+                report(sector);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Erases a sector, checks that it is blank, programs a test pattern and checks that it was programmed.
+    /// Returns false if any of the checks fail.
+    async fn screen_sector(&mut self, index: u32) -> Result<bool, Error<S, P>> {
+        self.screen_sector_with(index, 0xA5).await
+    }
+
+    /// Like [W25q256jv::screen_sector], with the provided test pattern.
+    async fn screen_sector_with(&mut self, index: u32, pattern: u8) -> Result<bool, Error<S, P>> {
+        let address = index * SECTOR_SIZE;
+
+        match self.erase_sector(index).await {
+            Err(Error::ReadbackFail) => return Ok(false),
+            result => result?,
+        }
+
+        if !self.is_filled_with(address, SECTOR_SIZE, 0xFF).await? {
+            return Ok(false);
+        }
+
+        for page in (address..address + SECTOR_SIZE).step_by(PAGE_SIZE as usize) {
+            match self.write_pages(page, &[pattern; PAGE_SIZE as usize]).await {
+                Err((_, Error::ReadbackFail)) => return Ok(false),
+                Err((_, error)) => return Err(error),
+                Ok(_) => {}
+            }
+        }
+
+        self.is_filled_with(address, SECTOR_SIZE, pattern).await
+    }
+
+    /// Reads a unary counter, which is the number of cleared bits in a field of `max_bits` bits.
+    /// See [W25q256jv::increment_unary_counter].
+    ///
+    /// # Arguments
+    /// * `address` - Address of the first byte of the counter field.
+    /// * `max_bits` - Size of the counter field in bits.
+    pub async fn read_unary_counter(
+        &mut self,
+        mut address: u32,
+        max_bits: u32,
+    ) -> Result<u32, Error<S, P>> {
+        const CHUNK_SIZE: u32 = 64;
+
+        let len = max_bits.div_ceil(8);
+        check_slice(address, len as usize)?;
+
+        let mut buf = [0; CHUNK_SIZE as usize];
+        let end = address + len;
+        let mut count = 0;
+
+        while address < end {
+            let buf = &mut buf[..core::cmp::min(CHUNK_SIZE, end - address) as usize];
+            self.read(address, buf).await?;
+            address += buf.len() as u32;
+
+            count += buf.iter().map(|byte| byte.count_zeros()).sum::<u32>();
+        }
+
+        Ok(core::cmp::min(count, max_bits))
+    }
+
+    /// Increments a unary counter by clearing its next bit, starting with the most significant bit of the first byte.
+    /// As NOR flash can clear bits without erasing, an increment costs a single one-byte program instead of an erase.
+    ///
+    /// The counter field must be erased before the first increment. Once all `max_bits` bits are cleared,
+    /// the counter is exhausted and [Error::CounterExhausted] is returned. Erase the field to reset the counter.
+    ///
+    /// # Arguments
+    /// * `address` - Address of the first byte of the counter field.
+    /// * `max_bits` - Size of the counter field in bits.
+    ///
+    /// Returns the new value of the counter.
+    pub async fn increment_unary_counter(
+        &mut self,
+        address: u32,
+        max_bits: u32,
+    ) -> Result<u32, Error<S, P>> {
+        let count = self.read_unary_counter(address, max_bits).await?;
+        if count >= max_bits {
+            return Err(Error::CounterExhausted);
+        }
+
+        // All bits up to and including the next bit of the byte are cleared
+        let value = (0xFF_u16 >> (count % 8 + 1)) as u8;
+        self.write(address + count / 8, &[value]).await?;
+
+        Ok(count + 1)
+    }
+
+    /// Writes a chunk of bytes to the flash chip and verifies it by reading back only the bytes that changed.
+    ///
+    /// The prior contents are read first. Only the window from the first to the last byte that differs from
+    /// the prior contents is read back after writing, which saves bus traffic when only a few bytes change.
+    /// Nothing is written if the data matches the prior contents.
+    ///
+    /// # Arguments
+    /// * `address` - Address where the first byte of the data will be written.
+    /// * `data` - Slice of bytes that will be written.
+    /// * `scratch` - Buffer used for the prior contents and the readback. Must be at least as long as the data.
+    pub async fn write_and_verify_delta(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        scratch: &mut [u8],
+    ) -> Result<(), Error<S, P>> {
+        if scratch.len() < data.len() {
+            return Err(Error::BufferTooSmall);
+        }
+
+        let scratch = &mut scratch[..data.len()];
+        self.read(address, scratch).await?;
+
+        let differs = |(new, old): (&u8, &u8)| new != old;
+        let Some(first) = data.iter().zip(scratch.iter()).position(differs) else {
+            return Ok(());
+        };
+        let last = data
+            .iter()
+            .zip(scratch.iter())
+            .rposition(differs)
+            .unwrap_or(first);
+
+        self.write(address, data).await?;
+
+        let readback = &mut scratch[first..=last];
+        self.read(address + first as u32, readback).await?;
+
+        if readback != &data[first..=last] {
+            return Err(Error::ReadbackFail);
+        }
+
+        Ok(())
+    }
+
+    /// Writes a record of variable length: a little-endian 2-byte length prefix followed by the data.
+    /// This allows appending records to a log without a filesystem. The region must be erased.
+    ///
+    /// The data is written before the length prefix, so a record interrupted by a power loss reads as absent
+    /// rather than as a record with erased or partial data.
+    ///
+    /// # Arguments
+    /// * `address` - Address where the record starts.
+    /// * `data` - The data of the record. At most 0xFFFE bytes, as an erased length prefix marks the end of the log.
+    ///
+    /// Returns the address where the next record starts.
+    pub async fn write_record(&mut self, address: u32, data: &[u8]) -> Result<u32, Error<S, P>> {
+        if data.len() >= u16::MAX as usize {
+            return Err(Error::OutOfBounds);
+        }
+
+        check_slice(address, RECORD_HEADER_LEN as usize + data.len())?;
+
+        self.write(address + RECORD_HEADER_LEN, data).await?;
+        self.write(address, &(data.len() as u16).to_le_bytes())
+            .await?;
+
+        Ok(address + RECORD_HEADER_LEN + data.len() as u32)
+    }
+
+    /// Reads a record written by [W25q256jv::write_record].
+    ///
+    /// # Arguments
+    /// * `address` - Address where the record starts.
+    /// * `buf` - Slice that is going to be filled with the data of the record.
+    ///
+    /// Returns the length of the record, or None if the length prefix is erased, which marks the end of the log.
+    pub async fn read_record(
+        &mut self,
+        address: u32,
+        buf: &mut [u8],
+    ) -> Result<Option<usize>, Error<S, P>> {
+        let mut header = [0; RECORD_HEADER_LEN as usize];
+        self.read(address, &mut header).await?;
+
+        let len = u16::from_le_bytes(header);
+        if len == u16::MAX {
+            return Ok(None);
+        }
+
+        let len = len as usize;
+        if len > buf.len() {
+            return Err(Error::BufferTooSmall);
+        }
+
+        self.read(address + RECORD_HEADER_LEN, &mut buf[..len])
+            .await?;
+
+        Ok(Some(len))
+    }
+
+    /// Reads the whole chip into memory, e.g. for imaging a chip from a host via a USB-SPI bridge.
+    /// The reads are split into chunks of SECTOR_SIZE bytes.
+    #[cfg(feature = "std")]
+    pub async fn read_all(&mut self) -> Result<std::vec::Vec<u8>, Error<S, P>> {
+        let mut data = std::vec![0; CAPACITY as usize];
+
+        for (address, chunk) in (0..CAPACITY)
+            .step_by(SECTOR_SIZE as usize)
+            .zip(data.chunks_mut(SECTOR_SIZE as usize))
+        {
+            self.read(address, chunk).await?;
+        }
+
+        Ok(data)
+    }
+
+    /// Finds where the written data in a region ends, e.g. to continue appending to a log.
+    ///
+    /// The region is scanned in steps of PAGE_SIZE bytes, starting at `start`, for the first step that is fully erased.
+    ///
+    /// # Arguments
+    /// * `start` - Address of the first byte of the region.
+    /// * `len` - Length of the region in bytes.
+    ///
+    /// Returns the address of the first fully erased step, or None if the whole region is written.
+    pub async fn find_write_cursor(
+        &mut self,
+        start: u32,
+        len: u32,
+    ) -> Result<Option<u32>, Error<S, P>> {
+        check_slice(start, len as usize)?;
+
+        let end = start + len;
+
+        for address in (start..end).step_by(PAGE_SIZE as usize) {
+            let step_len = core::cmp::min(PAGE_SIZE, end - address);
+
+            if self.is_filled_with(address, step_len, 0xFF).await? {
+                return Ok(Some(address));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Erases a single sector, unless it is already blank. Saves erase cycles in idempotent write flows.
+    ///
+    /// # Arguments
+    /// * `index` - the index of the sector that needs to be erased.
+    ///
+    /// Returns whether the sector was erased.
+    pub async fn erase_sector_if_needed(&mut self, index: u32) -> Result<bool, Error<S, P>> {
+        if index >= N_SECTORS {
+            return Err(Error::OutOfBounds);
+        }
+
+        if self
+            .is_filled_with(index * SECTOR_SIZE, SECTOR_SIZE, 0xFF)
+            .await?
+        {
+            return Ok(false);
+        }
+
+        self.erase_sector(index).await?;
+
+        Ok(true)
+    }
+
+    /// Reads multiple chunks of bytes from non-contiguous addresses, e.g. the headers of several partitions.
+    /// All ranges are checked before anything is read. Each chunk is read with its own SPI transaction.
+    ///
+    /// # Arguments
+    /// * `requests` - Pairs of the address to read from and the slice that is going to be filled with the read bytes.
+    pub async fn read_scatter(
+        &mut self,
+        requests: &mut [(u32, &mut [u8])],
+    ) -> Result<(), Error<S, P>> {
+        for (address, buf) in requests.iter() {
+            check_slice(*address, buf.len())?;
+        }
+
+        for (address, buf) in requests.iter_mut() {
+            self.read(*address, buf).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks if the data can be programmed at the provided address without erasing,
+    /// i.e. if programming it only needs to clear bits.
+    async fn is_writable(&mut self, mut address: u32, data: &[u8]) -> Result<bool, Error<S, P>> {
+        const CHUNK_SIZE: usize = 64;
+
+        let mut buf = [0; CHUNK_SIZE];
+
+        for chunk in data.chunks(CHUNK_SIZE) {
+            let buf = &mut buf[..chunk.len()];
+            self.read(address, buf).await?;
+            address += chunk.len() as u32;
+
+            if buf.iter().zip(chunk).any(|(old, new)| old & new != *new) {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Writes a chunk of bytes as `NorFlash::write` does: erasing on demand if [W25q256jv::set_auto_erase_on_write]
+    /// is enabled, otherwise like [W25q256jv::write].
+    pub(crate) async fn write_nor_flash(
+        &mut self,
+        address: u32,
+        data: &[u8],
+    ) -> Result<(), Error<S, P>> {
+        if self.auto_erase_on_write {
+            self.write_erasing(address, data).await
+        } else {
+            self.write(address, data).await
+        }
+    }
+
+    /// Writes a chunk of bytes, erasing the sectors that can't be programmed as is.
+    /// The other contents of those sectors are preserved by reading them first.
+    /// See [W25q256jv::set_auto_erase_on_write].
+    async fn write_erasing(&mut self, address: u32, data: &[u8]) -> Result<(), Error<S, P>> {
+        let mut sector = [0; SECTOR_SIZE as usize];
+
+        self.program_region(address, data, &mut sector).await
+    }
+
+    /// Writes a chunk of bytes at any address, erasing only the sectors it touches that can't be programmed as is.
+    /// The bytes of those sectors outside of the written range are preserved by reading the sector into the scratch
+    /// buffer first (read-modify-write). Sectors the data can be programmed into without erasing are left alone.
+    ///
+    /// A power loss during the read-modify-write of a sector loses the preserved bytes of that sector.
+    ///
+    /// # Arguments
+    /// * `address` - Address where the first byte of the data will be written.
+    /// * `data` - Slice of bytes that will be written.
+    /// * `scratch` - Buffer for the contents of a sector.
+    pub async fn program_region(
+        &mut self,
+        mut address: u32,
+        mut data: &[u8],
+        scratch: &mut [u8; SECTOR_SIZE as usize],
+    ) -> Result<(), Error<S, P>> {
+        if data.len() > self.max_write_len {
+            return Err(Error::WriteTooLarge);
+        }
+        check_slice(address, data.len())?;
+
+        while !data.is_empty() {
+            let offset = (address % SECTOR_SIZE) as usize;
+            let len = core::cmp::min(SECTOR_SIZE as usize - offset, data.len());
+            let (part, rest) = data.split_at(len);
+
+            if self.is_writable(address, part).await? {
+                self.write_pages(address, part)
+                    .await
+                    .map_err(|(_, error)| error)?;
+            } else {
+                let sector_address = address - offset as u32;
+
+                self.read(sector_address, scratch).await?;
+                scratch[offset..offset + len].copy_from_slice(part);
+
+                self.erase_sector(sector_address / SECTOR_SIZE).await?;
+                self.write_pages(sector_address, scratch)
+                    .await
+                    .map_err(|(_, error)| error)?;
+            }
+
+            address += len as u32;
+            data = rest;
+        }
+
+        Ok(())
+    }
+
+    /// Reads a chunk of bytes like [W25q256jv::read], but in transactions of at most `chunk_size` bytes,
+    /// yielding to the embassy executor after each one. This lets other tasks run during multi-megabyte reads,
+    /// at the cost of the command overhead of every transaction.
+    ///
+    /// # Arguments
+    /// * `address` - Address where the first byte of the buf will be read.
+    /// * `buf` - Slice that is going to be filled with the read bytes.
+    /// * `chunk_size` - Maximum number of bytes read between yield points. A size of 0 is treated as 1.
+    #[cfg(feature = "embassy")]
+    pub async fn read_yielding(
+        &mut self,
+        mut address: u32,
+        buf: &mut [u8],
+        chunk_size: usize,
+    ) -> Result<(), Error<S, P>> {
+        check_slice(address, buf.len())?;
+
+        for chunk in buf.chunks_mut(chunk_size.max(1)) {
+            self.read(address, chunk).await?;
+            address += chunk.len() as u32;
+
+            embassy_futures::yield_now().await;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that addresses above 16MB reach the upper half of the chip rather than wrapping around to the lower half,
+    /// as they would if a command with a 3-byte address were used.
+    ///
+    /// A marker is written to the last sector and read back, and the same offset 16MB below is checked to be unchanged.
+    ///
+    /// This is destructive: the last sector is erased and left containing the marker.
+    /// If the addresses do wrap, the corresponding sector in the lower half is erased and written instead.
+    ///
+    /// Returns true if the addressing works as expected.
+    pub async fn verify_addressing(&mut self) -> Result<bool, Error<S, P>> {
+        const MARKER: [u8; 8] = *b"W25Q4BYT";
+        const ALIAS_OFFSET: u32 = 0x0100_0000;
+
+        let index = N_SECTORS - 1;
+        let address = index * SECTOR_SIZE;
+
+        let mut alias_before = [0; MARKER.len()];
+        self.read(address - ALIAS_OFFSET, &mut alias_before).await?;
+
+        self.erase_sector(index).await?;
+        self.write(address, &MARKER).await?;
+
+        let mut readback = [0; MARKER.len()];
+        self.read(address, &mut readback).await?;
+        let mut alias_after = [0; MARKER.len()];
+        self.read(address - ALIAS_OFFSET, &mut alias_after).await?;
+
+        Ok(readback == MARKER && alias_after == alias_before)
+    }
+
+    /// Erases the whole chip like [W25q256jv::erase_chip], then blank-checks a number of randomly chosen sectors.
+    /// This gives some confidence in the erase in a fraction of the time a full readback takes, e.g. on a production line.
+    ///
+    /// # Arguments
+    /// * `sample_count` - Number of sectors to check. Sectors may be chosen more than once.
+    /// * `rng` - Returns random numbers, from which the sectors to check are chosen.
+    ///
+    /// Returns [Error::ReadbackFail] if a checked sector isn't blank.
+    pub async fn erase_chip_sampled_verify(
+        &mut self,
+        sample_count: u32,
+        rng: &mut impl FnMut() -> u32,
+    ) -> Result<(), Error<S, P>> {
+        self.erase_chip().await?;
+
+        for _ in 0..sample_count {
+            let index = rng() % N_SECTORS;
+
+            if !self
+                .is_filled_with(index * SECTOR_SIZE, SECTOR_SIZE, 0xFF)
+                .await?
+            {
+                return Err(Error::ReadbackFail);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Computes the [crc32::Crc32] checksum of a region of the chip, reading it in small chunks.
+    ///
+    /// # Arguments
+    /// * `address` - Address of the first byte of the region.
+    /// * `len` - Length of the region in bytes.
+    pub async fn crc32(&mut self, address: u32, len: u32) -> Result<u32, Error<S, P>> {
+        self.crc32_with_progress(address, len, &mut |_| {}).await
+    }
+
+    /// Computes the [crc32::Crc32] checksum of a region of the chip like [W25q256jv::crc32], reporting the progress
+    /// after each chunk, e.g. to show the progress of verifying a large firmware image.
+    ///
+    /// # Arguments
+    /// * `address` - Address of the first byte of the region.
+    /// * `len` - Length of the region in bytes.
+    /// * `progress` - Called with the number of bytes processed so far after each chunk.
+    pub async fn crc32_with_progress(
+        &mut self,
+        mut address: u32,
+        len: u32,
+        progress: &mut impl FnMut(u32),
+    ) -> Result<u32, Error<S, P>> {
+        const CHUNK_SIZE: u32 = 64;
+
+        check_slice(address, len as usize)?;
+
+        let mut crc = crc32::Crc32::new();
+        let mut buf = [0; CHUNK_SIZE as usize];
+        let end = address + len;
+
+        while address < end {
+            let chunk_len = core::cmp::min(CHUNK_SIZE, end - address);
+            self.read_hashed(address, &mut buf[..chunk_len as usize], &mut crc)
+                .await?;
+            address += chunk_len;
+            progress(len - (end - address));
+        }
+
+        Ok(crc.finalize())
+    }
+
+    /// Writes a chunk of bytes like [W25q256jv::write] and returns a token describing the write,
+    /// so the data can be verified at a later, more convenient time with [W25q256jv::verify_token].
+    pub async fn write_token(
+        &mut self,
+        address: u32,
+        data: &[u8],
+    ) -> Result<WriteToken, Error<S, P>> {
+        self.write(address, data).await?;
+
+        Ok(WriteToken {
+            address,
+            len: data.len() as u32,
+            crc: crc32::Crc32::checksum(data),
+        })
+    }
+
+    /// Reads back the region described by the token and returns true if it still holds the written data.
+    pub async fn verify_token(&mut self, token: &WriteToken) -> Result<bool, Error<S, P>> {
+        Ok(self.crc32(token.address, token.len).await? == token.crc)
+    }
+
+    /// Erases a range of sectors like [W25q256jv::erase_range], checking a cancellation flag before each sector.
+    ///
+    /// If the flag is set, [Error::Cancelled] is returned once the current sector erase has completed.
+    /// The sectors before it are erased then, while the sectors from it on are left untouched.
+    ///
+    /// # Arguments
+    /// * `start_address` - Address of the first byte of the start of the range of sectors that need to be erased.
+    /// * `end_address` - Address of the first byte of the end of the range of sectors that need to be erased.
+    /// * `cancel` - Flag that is set, e.g. by another task or an interrupt, to cancel the erase.
+    pub async fn erase_range_cancellable(
+        &mut self,
+        start_address: u32,
+        end_address: u32,
+        cancel: &core::sync::atomic::AtomicBool,
+    ) -> Result<(), Error<S, P>> {
+        check_erase(start_address, end_address, SECTOR_SIZE)?;
+
+        let start_sector = start_address / SECTOR_SIZE;
+        let end_sector = end_address / SECTOR_SIZE;
+
+        for sector in start_sector..end_sector {
+            if cancel.load(core::sync::atomic::Ordering::Relaxed) {
+                return Err(Error::Cancelled);
+            }
+
+            if sector != start_sector {
+                self.inter_command_delay().await;
+            }
+            self.erase_sector(sector).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads a whole page.
+    ///
+    /// # Arguments
+    /// * `index` - the index of the page. The address of the first byte of the page is the provided index * PAGE_SIZE.
+    /// * `buf` - Buffer that is going to be filled with the page.
+    pub async fn read_page(
+        &mut self,
+        index: u32,
+        buf: &mut [u8; PAGE_SIZE as usize],
+    ) -> Result<(), Error<S, P>> {
+        if index >= N_PAGES {
+            return Err(Error::OutOfBounds);
+        }
+
+        self.read(index * PAGE_SIZE, buf).await
+    }
+
+    /// Reads critical data stored twice, each copy followed by its [crc32::Crc32] checksum as 4 little-endian bytes.
+    /// The first copy is used if its checksum matches, otherwise the second copy.
+    ///
+    /// # Arguments
+    /// * `address_a` - Address of the first copy.
+    /// * `address_b` - Address of the second copy.
+    /// * `buf` - Slice that is going to be filled with the data. Its length is the length of the data without checksum.
+    ///
+    /// Returns true if the second copy was used, or [Error::ReadbackFail] if neither copy is valid.
+    pub async fn read_redundant(
+        &mut self,
+        address_a: u32,
+        address_b: u32,
+        buf: &mut [u8],
+    ) -> Result<bool, Error<S, P>> {
+        for (address, fallback) in [(address_a, false), (address_b, true)] {
+            check_slice(address, buf.len() + 4)?;
+
+            self.read(address, buf).await?;
+            let mut crc = [0; 4];
+            self.read(address + buf.len() as u32, &mut crc).await?;
+
+            if crc32::Crc32::checksum(buf) == u32::from_le_bytes(crc) {
+                return Ok(fallback);
+            }
+        }
+
+        Err(Error::ReadbackFail)
+    }
+
+    /// Leaves the chip in a predictable, factory-default state before handing the SPI bus to another driver,
+    /// e.g. a bootloader or a driver on a different firmware image.
+    ///
+    /// The following steps are taken:
+    /// 1. Wait for any ongoing operation to complete.
+    /// 2. Clear the write-enable flag.
+    /// 3. Exit 4-byte address mode, if the chip is in it.
+    /// 4. Optionally clear the Quad Enable bit, if it is set. This writes status register 2 non-volatile.
+    /// 5. Deassert the HOLD pin by driving it high, as well as the WP pin unless it was never driven,
+    ///    see [W25q256jv::new_no_wp_drive].
+    pub async fn release_bus(&mut self, clear_quad_enable: bool) -> Result<(), Error<S, P>> {
+        self.wait_ready().await?;
+        self.disable_write().await?;
+
+        if self.in_4_byte_address_mode().await? {
+            self.exit_4_byte_address_mode().await?;
+        }
+
+        if clear_quad_enable {
+            self.clear_quad_enable().await?;
+        }
+
+        self.set_hold(PinState::High)?;
+        if self.wp_state.is_some() {
+            self.set_wp(PinState::High)?;
+        }
+
+        Ok(())
+    }
+
+    /// Derives a stable 128-bit identifier of the chip from its unique ID and JEDEC ID,
+    /// e.g. for device registration or as input when provisioning keys.
+    ///
+    /// The IDs are hashed with 128-bit FNV-1a, which is not cryptographically secure.
+    /// The unique ID can be read by anyone with access to the chip, so the fingerprint is not a secret either.
+    pub async fn device_fingerprint(&mut self) -> Result<[u8; 16], Error<S, P>> {
+        const FNV_OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+        const FNV_PRIME: u128 = 0x0000000001000000000000000000013B;
+
+        let unique_id = self.read_unique_id().await?;
+        let jedec_id = self.read_jedec_id_bytes().await?;
+
+        let hash = unique_id
+            .iter()
+            .chain(jedec_id.iter())
+            .fold(FNV_OFFSET_BASIS, |hash, &byte| {
+                (hash ^ byte as u128).wrapping_mul(FNV_PRIME)
+            });
+
+        Ok(hash.to_be_bytes())
+    }
+
+    /// Writes a chunk of bytes like [W25q256jv::write], then reads it back and reports the first byte that differs.
+    /// This is meant for debugging corruption, as it tells where and how the data went wrong.
+    ///
+    /// # Arguments
+    /// * `address` - Address where the first byte of the data will be written.
+    /// * `data` - Slice of bytes that will be written.
+    /// * `scratch` - Buffer the data is read back into, in chunks of its length. Must not be empty.
+    ///
+    /// Returns the address, the expected and the found value of the first differing byte, or None if all bytes match.
+    pub async fn write_with_diff(
+        &mut self,
+        mut address: u32,
+        data: &[u8],
+        scratch: &mut [u8],
+    ) -> Result<Option<(u32, u8, u8)>, Error<S, P>> {
+        if scratch.is_empty() {
+            return Err(Error::BufferTooSmall);
+        }
+
+        self.write(address, data).await?;
+
+        for chunk in data.chunks(scratch.len()) {
+            let buf = &mut scratch[..chunk.len()];
+            self.read(address, buf).await?;
+
+            if let Some((offset, (expected, found))) = chunk
+                .iter()
+                .zip(buf.iter())
+                .enumerate()
+                .find(|(_, (expected, found))| expected != found)
+            {
+                return Ok(Some((address + offset as u32, *expected, *found)));
+            }
+
+            address += chunk.len() as u32;
+        }
+
+        Ok(None)
+    }
+
+    /// Runs a March-style memory test on a sector to screen a suspect chip: the sector is erased and checked to be blank,
+    /// then programmed with 0x00, 0x55 and 0xAA in turn, each checked and erased again. This exercises every bit
+    /// in both states and catches bits stuck at either value as well as shorts between neighbouring bits.
+    ///
+    /// This is destructive: the contents of the sector are lost and it is left erased.
+    /// Each pass costs an erase cycle.
+    ///
+    /// Returns whether the sector passed.
+    pub async fn march_test_sector(&mut self, index: u32) -> Result<bool, Error<S, P>> {
+        if index >= N_SECTORS {
+            return Err(Error::OutOfBounds);
+        }
+
+        for pattern in [0x00, 0x55, 0xAA] {
+            if !self.screen_sector_with(index, pattern).await? {
+                return Ok(false);
+            }
+        }
+
+        match self.erase_sector(index).await {
+            Err(Error::ReadbackFail) => return Ok(false),
+            result => result?,
+        }
+
+        self.is_filled_with(index * SECTOR_SIZE, SECTOR_SIZE, 0xFF)
+            .await
+    }
+
+    /// Programs a chunk of bytes, then reads it back with both [W25q256jv::read] and [W25q256jv::fast_read].
+    /// Data that only one of them reads back correctly points to a timing or dummy cycle problem of that read path.
+    ///
+    /// Returns [Error::ReadbackFail] if either read doesn't match the data.
+    pub async fn cross_read_verify(
+        &mut self,
+        mut address: u32,
+        data: &[u8],
+    ) -> Result<(), Error<S, P>> {
+        const CHUNK_SIZE: usize = 64;
+
+        self.write(address, data).await?;
+
+        let mut normal = [0; CHUNK_SIZE];
+        let mut fast = [0; CHUNK_SIZE];
+
+        for chunk in data.chunks(CHUNK_SIZE) {
+            let normal = &mut normal[..chunk.len()];
+            let fast = &mut fast[..chunk.len()];
+            self.read(address, normal).await?;
+            self.fast_read(address, fast).await?;
+
+            if normal != chunk || fast != chunk {
+                return Err(Error::ReadbackFail);
+            }
+
+            address += chunk.len() as u32;
+        }
+
+        Ok(())
+    }
+
+    /// Puts the chip into deep power-down, where it draws about 1 uA, e.g. between bursts of a battery-powered logger.
+    ///
+    /// An ongoing operation is completed first, as the chip ignores the command while busy. In power-down, the chip
+    /// only accepts the Release Power-down command, see [W25q256jv::release_power_down].
+    ///
+    /// # Arguments
+    /// * `delay` - Delay source used to wait until the chip is in power-down (tDP).
+    pub async fn power_down(&mut self, delay: &mut impl DelayNs) -> Result<(), Error<S, P>> {
+        self.begin_operation().await?;
+        self.wait_ready().await?;
+
+        self.spi
+            .write(&[Command::PowerDown as u8])
+            .await
+            .map_err(Error::SpiError)?;
+
+        // tDP, max 3us
+        delay.delay_us(3).await;
+
+        Ok(())
+    }
+
+    /// Wakes the chip from power-down. Use [W25q256jv::wake_and_identify] to also read the device ID.
+    ///
+    /// # Arguments
+    /// * `delay` - Delay source used to wait until the chip accepts other commands again (tRES1).
+    pub async fn release_power_down(
+        &mut self,
+        delay: &mut impl DelayNs,
+    ) -> Result<(), Error<S, P>> {
+        self.spi
+            .write(&[Command::ReleasePowerDown as u8])
+            .await
+            .map_err(Error::SpiError)?;
+
+        // tRES1, max 3us
+        delay.delay_us(3).await;
+
+        Ok(())
+    }
+
+    /// Wakes the chip from power-down with the Release Power-down / Device ID command and returns the legacy
+    /// device ID it reports, which is [DEVICE_ID] for the W25Q256JV. A different ID means the chip didn't wake up
+    /// correctly or isn't there.
+    ///
+    /// # Arguments
+    /// * `delay` - Delay source used to wait until the chip accepts other commands again (tRES2).
+    pub async fn wake_and_identify(&mut self, delay: &mut impl DelayNs) -> Result<u8, Error<S, P>> {
+        // The device ID follows 3 dummy bytes
+        let mut buf = [Command::ReleasePowerDown as u8, 0, 0, 0, 0];
+
+        self.spi
+            .transfer_in_place(&mut buf)
+            .await
+            .map_err(Error::SpiError)?;
+
+        // tRES2, max 1.8us
+        delay.delay_us(3).await;
+
+        Ok(buf[4])
+    }
+
+    /// Updates data stored in a sector without the risk of losing it on power loss, using a second sector as shadow.
+    ///
+    /// The data is first written to the shadow sector and verified, then to the primary sector. Each copy is followed
+    /// by its [crc32::Crc32] checksum as 4 little-endian bytes and a commit marker, which is only programmed once
+    /// the copy is verified. At any point in time, at least one of the sectors holds a committed copy, either of the
+    /// old or of the new data. Use [W25q256jv::read_committed] to read it back.
+    ///
+    /// # Arguments
+    /// * `primary` - Index of the primary sector.
+    /// * `shadow` - Index of the shadow sector. Must be a different sector than the primary one.
+    /// * `data` - The data. At most SECTOR_SIZE - 8 bytes, and always of the same length for the same pair of sectors.
+    pub async fn shadow_commit(
+        &mut self,
+        primary: u32,
+        shadow: u32,
+        data: &[u8],
+    ) -> Result<(), Error<S, P>> {
+        // With a single sector, erasing one copy would erase the other one as well
+        if data.len() > (SECTOR_SIZE - 8) as usize || primary == shadow {
+            return Err(Error::OutOfBounds);
+        }
+
+        self.write_committed_copy(shadow, data).await?;
+        self.write_committed_copy(primary, data).await
+    }
+
+    /// Reads data stored with [W25q256jv::shadow_commit] from the primary sector,
+    /// or from the shadow sector if the primary copy isn't committed or doesn't match its checksum.
+    ///
+    /// # Arguments
+    /// * `primary` - Index of the primary sector.
+    /// * `shadow` - Index of the shadow sector.
+    /// * `buf` - Slice that is going to be filled with the data. Its length is the length of the data.
+    ///
+    /// Returns true if the shadow copy was used, or [Error::ReadbackFail] if neither copy is valid.
+    pub async fn read_committed(
+        &mut self,
+        primary: u32,
+        shadow: u32,
+        buf: &mut [u8],
+    ) -> Result<bool, Error<S, P>> {
+        if buf.len() > (SECTOR_SIZE - 8) as usize {
+            return Err(Error::OutOfBounds);
+        }
+
+        for (index, fallback) in [(primary, false), (shadow, true)] {
+            if index >= N_SECTORS {
+                return Err(Error::OutOfBounds);
+            }
+
+            let address = index * SECTOR_SIZE;
+            let mut trailer = [0; 8];
+            self.read(address + buf.len() as u32, &mut trailer).await?;
+
+            if trailer[4..] != COMMIT_MARKER {
+                continue;
+            }
+
+            self.read(address, buf).await?;
+            let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+
+            if crc32::Crc32::checksum(buf) == crc {
+                return Ok(fallback);
+            }
+        }
+
+        Err(Error::ReadbackFail)
+    }
+
+    /// Writes a copy of the data, its checksum and, once the copy is verified, the commit marker to a sector.
+    /// See [W25q256jv::shadow_commit].
+    async fn write_committed_copy(&mut self, index: u32, data: &[u8]) -> Result<(), Error<S, P>> {
+        let address = index * SECTOR_SIZE;
+        let crc = crc32::Crc32::checksum(data);
+
+        self.erase_sector(index).await?;
+        self.write(address, data).await?;
+        self.write(address + data.len() as u32, &crc.to_le_bytes())
+            .await?;
+
+        if self.crc32(address, data.len() as u32).await? != crc {
+            return Err(Error::ReadbackFail);
+        }
+
+        self.write(address + data.len() as u32 + 4, &COMMIT_MARKER)
+            .await
+    }
+
+    /// Gathers the IDs and the configuration of the chip into a single snapshot, which can be logged with defmt
+    /// and pasted into a bug report.
+    #[cfg(feature = "diagnostics")]
+    pub async fn diagnostic_snapshot(&mut self) -> Result<Snapshot, Error<S, P>> {
+        let jedec_id = self.read_jedec_id_bytes().await?;
+        let unique_id = self.read_unique_id().await?;
+        let sr1 = self.read_status_register().await?;
+        let sr2 = self.read_status_register_2().await?;
+        let sr3 = self.read_status_register_3().await?;
+
+        Ok(Snapshot {
+            jedec_id,
+            unique_id,
+            status_registers: [sr1, sr2, sr3],
+            four_byte_address_mode: sr3 & 0x01 != 0,
+            quad_enabled: sr2 & 0x02 != 0,
+            protection: BlockProtection::from_status_registers(sr1, sr2),
+        })
+    }
+
+    /// Reads from one of the three one-time programmable security registers.
+    ///
+    /// # Arguments
+    /// * `register` - The number of the security register, 1 to 3.
+    /// * `offset` - Offset within the register where the first byte of the buf will be read.
+    /// * `buf` - Slice that is going to be filled with the read bytes. May not extend beyond SECURITY_REGISTER_SIZE.
+    pub async fn read_security_register(
+        &mut self,
+        register: u8,
+        offset: u32,
+        buf: &mut [u8],
+    ) -> Result<(), Error<S, P>> {
+        if !(1..=3).contains(&register)
+            || offset as u64 + buf.len() as u64 > SECURITY_REGISTER_SIZE as u64
+        {
+            return Err(Error::OutOfBounds);
+        }
+
+        // The address is followed by a dummy byte
+        let len = self
+            .security_register_command(Command::ReadSecurityRegister, register, offset)
+            .await?;
+
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&self.command[..len + 1]),
+                Operation::Read(buf),
+            ])
+            .await
+            .map_err(Error::SpiError)?;
+
+        Ok(())
+    }
+
+    /// Reads a human-readable serial number stored at the start of a security register, e.g. during provisioning.
+    /// The serial fills the buffer, or ends at the first erased (0xFF) or zero byte.
+    ///
+    /// # Arguments
+    /// * `register` - The number of the security register, 1 to 3.
+    /// * `buf` - Slice that is going to be filled with the read bytes. Its length is the maximum length of the serial.
+    ///
+    /// Returns the serial, or [Error::InvalidUtf8] if it isn't valid UTF-8.
+    pub async fn read_otp_serial<'b>(
+        &mut self,
+        register: u8,
+        buf: &'b mut [u8],
+    ) -> Result<&'b str, Error<S, P>> {
+        self.read_security_register(register, 0, buf).await?;
+
+        let len = buf
+            .iter()
+            .position(|&byte| byte == 0xFF || byte == 0x00)
+            .unwrap_or(buf.len());
+
+        core::str::from_utf8(&buf[..len]).map_err(|_| Error::InvalidUtf8)
+    }
+
+    /// Replaces the contents of a whole sector: erases it and programs its sixteen pages.
+    ///
+    /// The erase already waits for the chip to be ready, so the pages are programmed directly, without the status poll
+    /// [W25q256jv::write] issues before programming. Compared to [W25q256jv::erase_sector] followed by sixteen page
+    /// writes, this saves sixteen SPI transactions per sector, see [transactions_for_write].
+    ///
+    /// # Arguments
+    /// * `index` - the index of the sector. The address of the first byte of the sector is the provided index * SECTOR_SIZE.
+    /// * `data` - The new contents of the sector.
+    pub async fn replace_sector(
+        &mut self,
+        index: u32,
+        data: &[u8; SECTOR_SIZE as usize],
+    ) -> Result<(), Error<S, P>> {
+        self.erase_sector(index).await?;
+
+        let address = index * SECTOR_SIZE;
+
+        for (offset, page) in (0..SECTOR_SIZE)
+            .step_by(PAGE_SIZE as usize)
+            .zip(data.chunks(PAGE_SIZE as usize))
+        {
+            if offset != 0 {
+                self.inter_command_delay().await;
+            }
+            self.write_page(address + offset, page).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Computes the [crc32::Crc32] checksum of a region and writes it as 4 little-endian bytes to a footer,
+    /// e.g. to keep the integrity footer of a region current after parts of it were updated in place.
+    ///
+    /// # Arguments
+    /// * `region_start` - Address of the first byte of the region.
+    /// * `region_len` - Length of the region in bytes.
+    /// * `footer_address` - Address of the footer. The 4 bytes there must be erased.
+    ///
+    /// Returns the written checksum.
+    pub async fn update_region_crc(
+        &mut self,
+        region_start: u32,
+        region_len: u32,
+        footer_address: u32,
+    ) -> Result<u32, Error<S, P>> {
+        check_slice(footer_address, 4)?;
+
+        let crc = self.crc32(region_start, region_len).await?;
+        self.write(footer_address, &crc.to_le_bytes()).await?;
+
+        Ok(crc)
+    }
+
+    /// Reads a chunk of bytes like [W25q256jv::read], then reads it again to compare, for noisy environments
+    /// where a single read may glitch. On a mismatch, the read is retried.
+    ///
+    /// # Arguments
+    /// * `address` - Address where the first byte of the buf will be read.
+    /// * `buf` - Slice that is going to be filled with the read bytes.
+    /// * `retries` - How often the read is retried before giving up.
+    ///
+    /// Returns [Error::ReadbackFail] if no two consecutive reads matched.
+    pub async fn read_reliable(
+        &mut self,
+        address: u32,
+        buf: &mut [u8],
+        retries: u8,
+    ) -> Result<(), Error<S, P>> {
+        for _ in 0..=retries {
+            self.read(address, buf).await?;
+
+            match self.readback_check(address, buf).await {
+                Err(Error::ReadbackFail) => continue,
+                result => return result,
+            }
+        }
+
+        Err(Error::ReadbackFail)
+    }
+
+    /// Frames a security register command with the address of the provided register and offset.
+    /// The registers are at 0x1000, 0x2000 and 0x3000. The address has 4 bytes in 4-byte address mode.
+    ///
+    /// The frame is built in the command buffer, zero-padded to MAX_COMMAND_LEN.
+    /// Returns the length of the command and address.
+    async fn security_register_command(
+        &mut self,
+        command: Command,
+        register: u8,
+        offset: u32,
+    ) -> Result<usize, Error<S, P>> {
+        let address = (register as u32) << 12 | offset;
+        let four_byte_address_mode = self.in_4_byte_address_mode().await?;
+
+        self.command = [0; MAX_COMMAND_LEN];
+        if four_byte_address_mode {
+            command_and_address(&mut self.command, command as u8, address);
+            Ok(5)
+        } else {
+            command_and_address(&mut self.command, command as u8, address << 8);
+            Ok(4)
+        }
+    }
+
+    /// Erases one of the security registers, unless it is locked.
+    async fn erase_security_register(&mut self, register: u8) -> Result<(), Error<S, P>> {
+        self.begin_operation().await?;
+        self.wait_ready().await?;
+        self.enable_write().await?;
+
+        let len = self
+            .security_register_command(Command::EraseSecurityRegister, register, 0)
+            .await?;
+
+        self.spi
+            .write(&self.command[..len])
+            .await
+            .map_err(Error::SpiError)?;
+        self.in_operation = true;
+
+        // typical 50ms, max 400ms
+        self.wait_done(self.timing.sector_erase_us).await
+    }
+
+    /// Programs data into one of the security registers from its start, unless it is locked.
+    async fn program_security_register(
+        &mut self,
+        register: u8,
+        data: &[u8],
+    ) -> Result<(), Error<S, P>> {
+        self.begin_operation().await?;
+        self.wait_ready().await?;
+        self.enable_write().await?;
+
+        let len = self
+            .security_register_command(Command::ProgramSecurityRegister, register, 0)
+            .await?;
+
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&self.command[..len]),
+                Operation::Write(data),
+            ])
+            .await
+            .map_err(Error::SpiError)?;
+        self.in_operation = true;
+
+        // typical 0.7ms, max 3ms
+        self.wait_done(self.timing.page_program_us).await
+    }
+
+    /// Provisions the security registers with a blob of up to 3 * SECURITY_REGISTER_SIZE bytes,
+    /// e.g. serial numbers, calibration data or keys.
+    ///
+    /// The blob is split across the registers in order, starting with register 1. Each register that receives data
+    /// is erased, programmed and read back, returning [Error::ReadbackFail] if it doesn't hold the data, e.g. because
+    /// it is locked already.
+    ///
+    /// # Arguments
+    /// * `data` - The blob to provision.
+    /// * `lock` - Permanently lock the registers that received data afterwards, see [W25q256jv::security_lock_bits].
+    ///   Locking is irreversible: the registers can never be erased or programmed again.
+    pub async fn provision_otp(&mut self, data: &[u8], lock: bool) -> Result<(), Error<S, P>> {
+        if data.len() > 3 * SECURITY_REGISTER_SIZE as usize {
+            return Err(Error::OutOfBounds);
+        }
+
+        let mut lock_bits = 0;
+
+        for (register, chunk) in (1..=3).zip(data.chunks(SECURITY_REGISTER_SIZE as usize)) {
+            self.erase_security_register(register).await?;
+            self.program_security_register(register, chunk).await?;
+
+            let mut readback = [0; SECURITY_REGISTER_SIZE as usize];
+            let readback = &mut readback[..chunk.len()];
+            self.read_security_register(register, 0, readback).await?;
+            if readback != chunk {
+                return Err(Error::ReadbackFail);
+            }
+
+            // LB1 to LB3 are bits 3 to 5 of status register 2
+            lock_bits |= 0x04 << register;
+        }
+
+        if lock && lock_bits != 0 {
+            let sr2 = self.read_status_register_2().await?;
+            self.write_status_register(Command::WriteStatusRegister2, sr2 | lock_bits)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether a sector is marked as written by the convention of storing a marker in its first byte,
+    /// e.g. to tell whether a sector has been initialized. This reads a single byte rather than blank-checking the sector.
+    ///
+    /// # Arguments
+    /// * `index` - the index of the sector. The address of the first byte of the sector is the provided index * SECTOR_SIZE.
+    /// * `marker` - The value of the first byte of a written sector. Must not be 0xFF, the value of an erased byte.
+    pub async fn sector_marked_written(
+        &mut self,
+        index: u32,
+        marker: u8,
+    ) -> Result<bool, Error<S, P>> {
+        if index >= N_SECTORS {
+            return Err(Error::OutOfBounds);
+        }
+
+        let mut first = [0; 1];
+        self.read(index * SECTOR_SIZE, &mut first).await?;
+
+        Ok(first[0] == marker)
+    }
+
+    /// Reads the JEDEC ID repeatedly to check that the connection to the chip is stable, e.g. during board bring-up.
+    /// A single read may pass by coincidence on a flaky connection.
+    ///
+    /// Returns true only if every read returns [JEDEC_ID].
+    pub async fn bus_stability_check(&mut self, iterations: u32) -> Result<bool, Error<S, P>> {
+        for _ in 0..iterations {
+            if !self.read_jedec_id().await?.is_w25q256jv() {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+impl<SPI, S: Debug, P: Debug, HOLD, WP, DELAY> W25q256jv<SPI, HOLD, WP, DELAY>
+where
+    SPI: embedded_hal::spi::SpiDevice<Error = S>,
+    HOLD: OutputPin<Error = P>,
+    WP: OutputPin<Error = P>,
+    S: Debug,
+    P: Debug,
+{
+    /// Reads the JEDEC ID of the flash chip like [W25q256jv::read_jedec_id], blocking until the transfer is done.
+    /// Useful for early board bring-up code that runs before an executor is available.
+    pub fn read_jedec_id_blocking(&mut self) -> Result<JedecId, Error<S, P>> {
+        let mut buf = [0; 4];
+        buf[0] = Command::ReadJedecId as u8;
+
+        self.spi
+            .transfer_in_place(&mut buf)
+            .map_err(Error::SpiError)?;
+
+        Ok([buf[1], buf[2], buf[3]].into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::mock;
+    use embassy_futures::block_on;
+
+    /// Number of reads that check one page, see [W25q256jv::is_filled_with].
+    const READS_PER_PAGE: usize = PAGE_SIZE as usize / 64;
+
+    #[test]
+    fn is_chip_erased_samples_first_and_last_page_of_every_interval() {
+        let (sim, mut flash) = mock::flash();
+
+        assert!(block_on(flash.is_chip_erased()).unwrap());
+
+        let samples = (CAPACITY / BLOCK_64K_SIZE) as usize * 2;
+        assert_eq!(sim.borrow().count(0x13), samples * READS_PER_PAGE);
+
+        let addresses = sim.borrow().read_addresses();
+        assert_eq!(addresses[0], 0);
+        assert_eq!(
+            addresses[READS_PER_PAGE],
+            BLOCK_64K_SIZE - PAGE_SIZE,
+            "the second sample is the last page of the first interval"
+        );
+    }
+
+    #[test]
+    fn is_chip_erased_reads_every_page_with_page_interval() {
+        let (sim, mut flash) = mock::flash();
+        flash.set_chip_erase_sample_interval(PAGE_SIZE).unwrap();
+
+        assert!(block_on(flash.is_chip_erased()).unwrap());
+        assert_eq!(sim.borrow().count(0x13), N_PAGES as usize * READS_PER_PAGE);
+    }
+
+    #[test]
+    fn is_chip_erased_misses_data_between_samples() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().set_memory(BLOCK_64K_SIZE / 2, &[0x00]);
+
+        assert!(block_on(flash.is_chip_erased()).unwrap());
+
+        flash.set_chip_erase_sample_interval(PAGE_SIZE).unwrap();
+        assert!(!block_on(flash.is_chip_erased()).unwrap());
+    }
+
+    #[test]
+    fn is_chip_erased_detects_data_in_sampled_page() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut()
+            .set_memory(3 * BLOCK_64K_SIZE - PAGE_SIZE + 17, &[0x7F]);
+
+        assert!(!block_on(flash.is_chip_erased()).unwrap());
+        // Stops at the first sample that isn't erased
+        assert_eq!(
+            *sim.borrow().read_addresses().last().unwrap(),
+            3 * BLOCK_64K_SIZE - PAGE_SIZE
+        );
+    }
+
+    #[test]
+    fn framed_commands_match_the_datasheet() {
+        let (sim, mut flash) = mock::flash();
+
+        block_on(flash.read_sfdp(0x123456, &mut [0; 4])).unwrap();
+        block_on(flash.read_unique_id()).unwrap();
+        block_on(flash.read_security_register(2, 0x10, &mut [0; 4])).unwrap();
+        block_on(flash.fast_read(0x0123_4567, &mut [0; 4])).unwrap();
+
+        let chip = sim.borrow();
+        assert_eq!(chip.commands(0x5A), [[0x5A, 0x12, 0x34, 0x56, 0x00]]);
+        assert_eq!(chip.commands(0x4B), [[0x4B, 0, 0, 0, 0]]);
+        assert_eq!(chip.commands(0x48), [[0x48, 0x00, 0x20, 0x10, 0x00]]);
+        assert_eq!(chip.commands(0x0C), [[0x0C, 0x01, 0x23, 0x45, 0x67, 0x00]]);
+    }
+
+    #[test]
+    fn framed_commands_in_4_byte_address_mode() {
+        let (sim, mut flash) = mock::flash();
+        block_on(flash.enter_4_byte_address_mode()).unwrap();
+
+        block_on(flash.read_unique_id()).unwrap();
+        block_on(flash.read_security_register(3, 0xFF, &mut [0; 1])).unwrap();
+
+        let chip = sim.borrow();
+        assert_eq!(chip.commands(0x4B), [[0x4B, 0, 0, 0, 0, 0]]);
+        assert_eq!(chip.commands(0x48), [[0x48, 0x00, 0x00, 0x30, 0xFF, 0x00]]);
+    }
+
+    #[test]
+    fn framed_reads_return_the_same_data() {
+        let (sim, mut flash) = mock::flash();
+        let data: [u8; 64] = core::array::from_fn(|i| i as u8);
+        sim.borrow_mut().set_memory(0x0100_0000 - 32, &data);
+        sim.borrow_mut().unique_id = [8, 7, 6, 5, 4, 3, 2, 1];
+        sim.borrow_mut().sfdp = std::vec![0x53, 0x46, 0x44, 0x50];
+
+        let mut read = [0; 64];
+        let mut fast_read = [0; 64];
+        block_on(flash.read(0x0100_0000 - 32, &mut read)).unwrap();
+        // Leaves a different frame in the command buffer before every command
+        block_on(flash.read_sfdp(0, &mut [0; 4])).unwrap();
+        block_on(flash.fast_read(0x0100_0000 - 32, &mut fast_read)).unwrap();
+        assert_eq!(read, data);
+        assert_eq!(fast_read, data);
+
+        assert_eq!(
+            block_on(flash.read_unique_id()).unwrap(),
+            [8, 7, 6, 5, 4, 3, 2, 1]
+        );
+        let mut sfdp = [0; 4];
+        block_on(flash.read_sfdp(0, &mut sfdp)).unwrap();
+        assert_eq!(&sfdp, b"SFDP");
+    }
+
+    /// Sums all bytes it is fed, and counts the chunks.
+    #[derive(Default)]
+    struct SumDigest {
+        sum: u32,
+        chunks: usize,
+    }
+
+    impl Digest for SumDigest {
+        fn update(&mut self, data: &[u8]) {
+            self.sum += data.iter().map(|&byte| byte as u32).sum::<u32>();
+            self.chunks += 1;
+        }
+    }
+
+    #[test]
+    fn read_hashed_feeds_the_read_data_into_the_digest() {
+        let (sim, mut flash) = mock::flash();
+        let data: [u8; 100] = core::array::from_fn(|i| i as u8);
+        sim.borrow_mut().set_memory(0x1000, &data);
+
+        let mut buf = [0; 100];
+        let mut digest = SumDigest::default();
+        block_on(flash.read_hashed(0x1000, &mut buf, &mut digest)).unwrap();
+
+        assert_eq!(buf, data);
+        assert_eq!(digest.sum, (0..100).sum::<u32>());
+        // A single pass over the chip
+        assert_eq!(sim.borrow().count(0x13), 1);
+
+        block_on(flash.read_hashed(0x1000, &mut buf[..10], &mut digest)).unwrap();
+        assert_eq!(digest.sum, (0..100).sum::<u32>() + (0..10).sum::<u32>());
+        assert_eq!(digest.chunks, 2);
+    }
+
+    #[test]
+    fn erase_block_32k_above_16mb_uses_4_byte_address() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().set_memory(0x0100_0000, &[0x00; 16]);
+        sim.borrow_mut()
+            .set_memory(0x0100_0000 + BLOCK_32K_SIZE, &[0x00]);
+
+        block_on(flash.erase_block_32k(0x0100_0000 / BLOCK_32K_SIZE)).unwrap();
+
+        let chip = sim.borrow();
+        assert_eq!(chip.commands(0x5C), [[0x5C, 0x01, 0x00, 0x00, 0x00]]);
+        assert_eq!(chip.count(0x52), 0);
+        assert_eq!(chip.memory(0x0100_0000, 16), [0xFF; 16]);
+        assert_eq!(chip.memory(0x0100_0000 + BLOCK_32K_SIZE, 1), [0x00]);
+    }
+
+    #[test]
+    fn check_write_enable_latch_reports_wel() {
+        let (sim, mut flash) = mock::flash();
+
+        assert!(block_on(flash.check_write_enable_latch()).unwrap());
+        assert_eq!(sim.borrow().opcodes(), [0x05, 0x06, 0x05, 0x04]);
+        // Cleared again afterwards
+        assert!(!block_on(flash.write_enabled()).unwrap());
+
+        sim.borrow_mut().ignore_write_enable = true;
+        assert!(!block_on(flash.check_write_enable_latch()).unwrap());
+    }
+
+    #[test]
+    fn inter_command_delay_between_page_programs() {
+        let (sim, mut flash) = mock::flash_with_delay(500);
+
+        block_on(flash.write(0x80, &[0x55; 2 * PAGE_SIZE as usize])).unwrap();
+
+        let chip = sim.borrow();
+        let mut gaps = 0;
+        let mut programs = 0;
+        for event in &chip.log {
+            match event {
+                mock::Event::Delay(500) => gaps += 1,
+                mock::Event::Transaction { tx, .. } if tx[0] == 0x12 => {
+                    assert_eq!(gaps, programs, "one gap before every page but the first");
+                    programs += 1;
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(programs, 3);
+        assert_eq!(gaps, 2);
+    }
+
+    #[test]
+    fn inter_command_delay_between_sector_erases() {
+        let (sim, mut flash) = mock::flash_with_delay(500);
+
+        block_on(flash.erase_range(0, 3 * SECTOR_SIZE)).unwrap();
+
+        let chip = sim.borrow();
+        assert_eq!(chip.count(0x21), 3);
+        assert_eq!(chip.delays().iter().filter(|&&ns| ns == 500).count(), 2);
+    }
+
+    #[test]
+    fn no_inter_command_delay_by_default() {
+        let (sim, mut flash) = mock::flash_with_delay(0);
+
+        block_on(flash.write(0x80, &[0x55; 2 * PAGE_SIZE as usize])).unwrap();
+
+        assert!(sim.borrow().delays().iter().all(|&ns| ns != 0));
+    }
+
+    #[test]
+    fn nor_flash_erase_reports_error_kinds() {
+        let (sim, mut flash) = mock::flash();
+
+        let error = block_on(NorFlash::erase(&mut flash, 1, SECTOR_SIZE)).unwrap_err();
+        assert_eq!(error.kind(), NorFlashErrorKind::NotAligned);
+
+        let error = block_on(NorFlash::erase(&mut flash, 0, SECTOR_SIZE + 1)).unwrap_err();
+        assert_eq!(error.kind(), NorFlashErrorKind::NotAligned);
+
+        let error = block_on(NorFlash::erase(&mut flash, 0, CAPACITY + SECTOR_SIZE)).unwrap_err();
+        assert_eq!(error.kind(), NorFlashErrorKind::OutOfBounds);
+
+        let error = block_on(NorFlash::erase(&mut flash, SECTOR_SIZE, 0)).unwrap_err();
+        assert_eq!(error.kind(), NorFlashErrorKind::OutOfBounds);
+
+        // Rejected before anything was sent to the chip
+        assert_eq!(sim.borrow().transactions(), 0);
+    }
+
+    #[test]
+    fn read_single_transfer_reads_in_one_operation() {
+        let (sim, mut flash) = mock::flash();
+        let data: [u8; 32] = core::array::from_fn(|i| 0x80 | i as u8);
+        sim.borrow_mut().set_memory(0x0123_4500, &data);
+
+        let mut frame = [0xAA; READ_COMMAND_LEN + 32];
+        let read = block_on(flash.read_single_transfer(0x0123_4500, &mut frame)).unwrap();
+        assert_eq!(read, data);
+
+        let mut buf = [0; 32];
+        block_on(flash.read(0x0123_4500, &mut buf)).unwrap();
+        assert_eq!(buf, data);
+
+        let frames = sim.borrow().frames();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].len(), READ_COMMAND_LEN + 32);
+        assert_eq!(frames[0][..READ_COMMAND_LEN], frames[1]);
+    }
+
+    #[test]
+    fn read_single_transfer_rejects_short_frames() {
+        let (sim, mut flash) = mock::flash();
+
+        let mut frame = [0; READ_COMMAND_LEN - 1];
+        assert!(block_on(flash.read_single_transfer(0, &mut frame))
+            .unwrap_err()
+            .is_out_of_bounds());
+
+        let mut frame = [0; READ_COMMAND_LEN + 2];
+        assert!(
+            block_on(flash.read_single_transfer(CAPACITY - 1, &mut frame))
+                .unwrap_err()
+                .is_out_of_bounds()
+        );
+        assert_eq!(sim.borrow().transactions(), 0);
+    }
+
+    #[test]
+    fn scan_for_errors_reports_the_failing_sector() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut()
+            .stuck_bits
+            .push((1234 * SECTOR_SIZE + 77, 0x01, 0x00));
+
+        let mut failed = std::vec::Vec::new();
+        block_on(flash.scan_for_errors(&mut |sector| failed.push(sector))).unwrap();
+
+        assert_eq!(failed, [1234]);
+        assert_eq!(sim.borrow().count(0x21), N_SECTORS as usize);
+    }
+
+    #[test]
+    fn unary_counter_increments_by_clearing_bits() {
+        let (sim, mut flash) = mock::flash();
+
+        assert_eq!(block_on(flash.read_unary_counter(0x2000, 12)).unwrap(), 0);
+        for expected in 1..=9 {
+            assert_eq!(
+                block_on(flash.increment_unary_counter(0x2000, 12)).unwrap(),
+                expected
+            );
+        }
+
+        assert_eq!(block_on(flash.read_unary_counter(0x2000, 12)).unwrap(), 9);
+        assert_eq!(sim.borrow().memory(0x2000, 3), [0x00, 0x7F, 0xFF]);
+        // Every increment is a single one-byte program, without an erase
+        assert_eq!(sim.borrow().count(0x12), 9);
+        assert!(sim
+            .borrow()
+            .commands(0x12)
+            .iter()
+            .all(|frame| frame.len() == 6));
+        assert_eq!(sim.borrow().count(0x21), 0);
+    }
+
+    #[test]
+    fn unary_counter_exhausts_at_max_bits() {
+        let (_, mut flash) = mock::flash();
+
+        for _ in 0..3 {
+            block_on(flash.increment_unary_counter(0x2000, 3)).unwrap();
+        }
+
+        assert!(matches!(
+            block_on(flash.increment_unary_counter(0x2000, 3)),
+            Err(Error::CounterExhausted)
+        ));
+        assert_eq!(block_on(flash.read_unary_counter(0x2000, 3)).unwrap(), 3);
+    }
+
+    #[test]
+    fn unary_counter_past_the_end_is_rejected() {
+        let (sim, mut flash) = mock::flash();
+
+        for address in [CAPACITY - 1, u32::MAX - 1] {
+            assert!(matches!(
+                block_on(flash.read_unary_counter(address, 16)),
+                Err(Error::OutOfBounds)
+            ));
+            assert!(matches!(
+                block_on(flash.increment_unary_counter(address, 16)),
+                Err(Error::OutOfBounds)
+            ));
+        }
+        assert_eq!(sim.borrow().transactions(), 0);
+    }
+
+    #[test]
+    fn wait_ready_resolves_once_busy_clears() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().set_busy(2);
+
+        let (result, ()) = block_on(embassy_futures::join::join(flash.wait_ready(), async {}));
+        result.unwrap();
+
+        let chip = sim.borrow();
+        assert!(!chip.is_busy());
+        // Two busy polls and the one that saw the chip ready
+        assert_eq!(chip.opcodes(), [0x05, 0x05, 0x05]);
+    }
+
+    #[test]
+    fn write_and_verify_delta_reads_back_only_the_changed_window() {
+        let (sim, mut flash) = mock::flash();
+        let prior: [u8; 256] = core::array::from_fn(|i| i as u8 | 0x80);
+        sim.borrow_mut().set_memory(0x3000, &prior);
+
+        let mut data = prior;
+        for i in [10, 50, 100] {
+            data[i] = 0x00;
+        }
+        let mut scratch = [0; 256];
+        block_on(flash.write_and_verify_delta(0x3000, &data, &mut scratch)).unwrap();
+
+        let chip = sim.borrow();
+        assert_eq!(chip.memory(0x3000, 256), data);
+        assert_eq!(*chip.read_addresses().last().unwrap(), 0x3000 + 10);
+        let last_read_len = chip.log.iter().rev().find_map(|event| match event {
+            mock::Event::Transaction { tx, read_len } if tx[0] == 0x13 => Some(*read_len),
+            _ => None,
+        });
+        assert_eq!(last_read_len, Some(91));
+    }
+
+    #[test]
+    fn write_and_verify_delta_skips_unchanged_data() {
+        let (sim, mut flash) = mock::flash();
+
+        block_on(flash.write_and_verify_delta(0x3000, &[0xFF; 16], &mut [0; 16])).unwrap();
+
+        assert_eq!(sim.borrow().opcodes(), [0x13]);
+    }
+
+    #[test]
+    fn write_and_verify_delta_detects_a_mismatch() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().stuck_bits.push((0x3005, 0x01, 0x01));
+
+        assert!(matches!(
+            block_on(flash.write_and_verify_delta(0x3000, &[0x00; 16], &mut [0; 16])),
+            Err(Error::ReadbackFail)
+        ));
+    }
+
+    #[test]
+    fn verify_writes_reads_back_programmed_pages() {
+        let (sim, mut flash) = mock::flash();
+
+        block_on(flash.write(0x100, &[0x42; 16])).unwrap();
+
+        let chip = sim.borrow();
+        if cfg!(feature = "verify-writes") {
+            assert_eq!(chip.read_addresses(), [0x100]);
+        } else {
+            assert_eq!(chip.count(0x13), 0);
+        }
+    }
+
+    #[test]
+    fn verify_erases_reads_back_erased_sectors() {
+        let (sim, mut flash) = mock::flash();
+
+        block_on(flash.erase_sector(1)).unwrap();
+
+        let chip = sim.borrow();
+        if cfg!(feature = "verify-erases") {
+            assert_eq!(chip.count(0x13), SECTOR_SIZE as usize / 64);
+            assert_eq!(chip.read_addresses()[0], SECTOR_SIZE);
+        } else {
+            assert_eq!(chip.count(0x13), 0);
+        }
+    }
+
+    #[test]
+    fn hold_reset_mode_is_bit_7_of_sr3() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().status_registers[2] = 0x60;
+
+        assert_eq!(
+            block_on(flash.hold_reset_mode()).unwrap(),
+            HoldResetMode::Hold
+        );
+
+        block_on(flash.set_hold_reset_mode(HoldResetMode::Reset)).unwrap();
+        assert_eq!(sim.borrow().commands(0x11), [[0x11, 0xE0]]);
+        assert_eq!(
+            block_on(flash.hold_reset_mode()).unwrap(),
+            HoldResetMode::Reset
+        );
+
+        block_on(flash.set_hold_reset_mode(HoldResetMode::Hold)).unwrap();
+        assert_eq!(sim.borrow().status_registers[2], 0x60);
+        assert_eq!(
+            block_on(flash.hold_reset_mode()).unwrap(),
+            HoldResetMode::Hold
+        );
+    }
+
+    #[test]
+    fn init_sequence_for_a_clean_bring_up() {
+        let (sim, mut flash) = mock::flash();
+        let mut delay = mock::Delay(sim.clone());
+
+        block_on(flash.init(&mut delay, InitConfig::default())).unwrap();
+
+        let chip = sim.borrow();
+        assert_eq!(chip.log[0], mock::Event::Delay(10_000_000));
+        assert_eq!(
+            chip.frames(),
+            [
+                std::vec![0xFF, 0xFF],
+                std::vec![0x05, 0x00],
+                std::vec![0x9F, 0x00, 0x00, 0x00],
+                std::vec![0x04]
+            ]
+        );
+    }
+
+    #[test]
+    fn init_sequence_with_all_options() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().status_registers[1] = 0x02;
+        let mut delay = mock::Delay(sim.clone());
+
+        let config = InitConfig {
+            exit_continuous_read: false,
+            enter_4_byte_address_mode: true,
+            clear_quad_enable: true,
+        };
+        block_on(flash.init(&mut delay, config)).unwrap();
+
+        let chip = sim.borrow();
+        assert_eq!(
+            chip.opcodes(),
+            [0x05, 0x9F, 0x04, 0x35, 0x05, 0x06, 0x05, 0x31, 0x05, 0x05, 0xB7, 0x15]
+        );
+        assert_eq!(chip.commands(0x31), [[0x31, 0x00]]);
+        assert_eq!(chip.status_registers[1] & 0x02, 0);
+        assert_eq!(chip.status_registers[2] & 0x01, 0x01);
+    }
+
+    #[test]
+    fn init_rejects_an_unexpected_id() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().jedec_id = [0xEF, 0x40, 0x18];
+        let mut delay = mock::Delay(sim.clone());
+
+        assert!(matches!(
+            block_on(flash.init(&mut delay, InitConfig::default())),
+            Err(Error::UnexpectedId {
+                read: [0xEF, 0x40, 0x18]
+            })
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "diagnostics")]
+    fn time_erase_sector_measures_until_busy_clears() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().busy_polls = 4;
+
+        // Ticks once per transaction on the bus
+        let clock = || sim.borrow().transactions() as u32;
+        let ticks = block_on(flash.time_erase_sector(3, clock)).unwrap();
+
+        // Four busy polls and the one that saw the chip ready
+        assert_eq!(ticks, 5);
+        assert_eq!(
+            sim.borrow().commands(0x21),
+            [[0x21, 0x00, 0x00, 0x30, 0x00]]
+        );
+        assert!(!sim.borrow().is_busy());
+    }
+
+    #[test]
+    fn read_up_to_capacity() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().set_memory(CAPACITY - 4, &[1, 2, 3, 4]);
+
+        let mut buf = [0; 4];
+        block_on(flash.read(CAPACITY - 4, &mut buf)).unwrap();
+        assert_eq!(buf, [1, 2, 3, 4]);
+
+        let mut buf = [0; 5];
+        assert!(block_on(flash.read(CAPACITY - 4, &mut buf))
+            .unwrap_err()
+            .is_out_of_bounds());
+        assert_eq!(sim.borrow().count(0x13), 1);
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let (_, mut flash) = mock::flash();
+
+        let mut next = 0x4000;
+        for record in [&b"first"[..], &[], &[0xAB; 300]] {
+            next = block_on(flash.write_record(next, record)).unwrap();
+        }
+        assert_eq!(next, 0x4000 + 3 * RECORD_HEADER_LEN + 5 + 300);
+
+        let mut buf = [0; 300];
+        let mut address = 0x4000;
+        for expected in [&b"first"[..], &[], &[0xAB; 300]] {
+            let len = block_on(flash.read_record(address, &mut buf))
+                .unwrap()
+                .unwrap();
+            assert_eq!(&buf[..len], expected);
+            address += RECORD_HEADER_LEN + len as u32;
+        }
+        assert_eq!(address, next);
+    }
+
+    #[test]
+    fn record_end_of_log() {
+        let (_, mut flash) = mock::flash();
+        let next = block_on(flash.write_record(0x4000, b"only")).unwrap();
+
+        let mut buf = [0; 16];
+        assert_eq!(block_on(flash.read_record(next, &mut buf)).unwrap(), None);
+        assert!(matches!(
+            block_on(flash.read_record(0x4000, &mut buf[..3])),
+            Err(Error::BufferTooSmall)
+        ));
+        assert!(
+            block_on(flash.write_record(0x4000, &[0; u16::MAX as usize]))
+                .unwrap_err()
+                .is_out_of_bounds()
+        );
+    }
+
+    #[test]
+    fn quad_enabled_decodes_qe() {
+        let (sim, mut flash) = mock::flash();
+
+        sim.borrow_mut().status_registers[1] = 0xFD;
+        assert!(!block_on(flash.quad_enabled()).unwrap());
+
+        sim.borrow_mut().status_registers[1] = 0x02;
+        assert!(block_on(flash.quad_enabled()).unwrap());
+    }
+
+    #[test]
+    fn init_clears_quad_enable_only_when_configured() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().status_registers[1] = 0x42;
+        let mut delay = mock::Delay(sim.clone());
+
+        block_on(flash.init(&mut delay, InitConfig::default())).unwrap();
+        assert!(block_on(flash.quad_enabled()).unwrap());
+        assert_eq!(sim.borrow().count(0x31), 0);
+
+        let config = InitConfig {
+            clear_quad_enable: true,
+            ..InitConfig::default()
+        };
+        block_on(flash.init(&mut delay, config)).unwrap();
+        assert!(!block_on(flash.quad_enabled()).unwrap());
+        // The other bits of status register 2 are kept
+        assert_eq!(sim.borrow().commands(0x31), [[0x31, 0x40]]);
+
+        // Nothing is written if the bit is clear already
+        block_on(flash.init(&mut delay, config)).unwrap();
+        assert_eq!(sim.borrow().count(0x31), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "verify-erases")]
+    fn erase_sector_retries_failed_verification() {
+        let (sim, mut flash) = mock::flash();
+        flash.set_erase_retries(2);
+        sim.borrow_mut().read_faults.push(mock::ReadFault {
+            address: 5 * SECTOR_SIZE + 100,
+            xor: 0x01,
+            opcode: Some(0x13),
+            remaining: 1,
+        });
+
+        block_on(flash.erase_sector(5)).unwrap();
+
+        assert_eq!(sim.borrow().count(0x21), 2, "one retry");
+    }
+
+    #[test]
+    #[cfg(feature = "verify-erases")]
+    fn erase_sector_fails_once_retries_are_exhausted() {
+        let (sim, mut flash) = mock::flash();
+        flash.set_erase_retries(2);
+        sim.borrow_mut().read_faults.push(mock::ReadFault {
+            address: 5 * SECTOR_SIZE + 100,
+            xor: 0x01,
+            opcode: Some(0x13),
+            remaining: usize::MAX,
+        });
+
+        assert!(matches!(
+            block_on(flash.erase_sector(5)),
+            Err(Error::ReadbackFail)
+        ));
+        assert_eq!(sim.borrow().count(0x21), 3);
+    }
+
+    #[test]
+    #[cfg(feature = "verify-erases")]
+    fn erase_sector_does_not_retry_by_default() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().read_faults.push(mock::ReadFault {
+            address: 5 * SECTOR_SIZE,
+            xor: 0x80,
+            opcode: None,
+            remaining: 1,
+        });
+
+        assert!(matches!(
+            block_on(flash.erase_sector(5)),
+            Err(Error::ReadbackFail)
+        ));
+        assert_eq!(sim.borrow().count(0x21), 1);
+    }
+
+    /// An SFDP table whose basic flash parameter table declares the typical times of the operations.
+    fn sfdp_with_timing() -> std::vec::Vec<u8> {
+        let mut sfdp = std::vec![0xFF; 0x80 + 16 * 4];
+        sfdp[..8].copy_from_slice(&[b'S', b'F', b'D', b'P', 0x06, 0x01, 0x00, 0xFF]);
+        // Parameter header of the basic flash parameter table: 16 DWORDs at 0x80
+        sfdp[8..16].copy_from_slice(&[0x00, 0x06, 0x01, 16, 0x80, 0x00, 0x00, 0xFF]);
+
+        let mut dword = |n: usize, value: u32| {
+            sfdp[0x80 + (n - 1) * 4..0x80 + n * 4].copy_from_slice(&value.to_le_bytes());
+        };
+        // Erase types 1 to 3: 4KB, 32KB and 64KB
+        dword(8, 0x52_0F_20_0C);
+        dword(9, 0x00_00_D8_10);
+        // 30 * 1ms, 7 * 16ms and 2 * 128ms
+        dword(
+            10,
+            (29 << 4) | (((1 << 5) | 6) << 11) | (((2 << 5) | 1) << 18),
+        );
+        // Page program 8 * 64us, chip erase 16 * 4s
+        dword(11, ((0x20 | 7) << 8) | (((2 << 5) | 15) << 24));
+
+        sfdp
+    }
+
+    #[test]
+    fn timing_profile_from_sfdp() {
+        let (sim, mut flash) = mock::flash_with_delay(0);
+        sim.borrow_mut().sfdp = sfdp_with_timing();
+
+        let profile = block_on(flash.load_timing_profile_from_sfdp()).unwrap();
+        assert_eq!(
+            profile,
+            TimingProfile {
+                page_program_us: 512,
+                sector_erase_us: 30_000,
+                block_32k_erase_us: 112_000,
+                block_64k_erase_us: 256_000,
+                chip_erase_us: 64_000_000,
+            }
+        );
+        assert_eq!(flash.timing_profile(), profile);
+
+        // The status is polled at a tenth of the typical time
+        sim.borrow_mut().log.clear();
+        block_on(flash.erase_sector(0)).unwrap();
+        block_on(flash.write(0, &[0; 4])).unwrap();
+        assert_eq!(sim.borrow().delays(), [3_000_000, 51_000]);
+    }
+
+    #[test]
+    fn timing_profile_requires_valid_sfdp() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().sfdp = std::vec![0xFF; 16];
+
+        assert!(matches!(
+            block_on(flash.load_timing_profile_from_sfdp()),
+            Err(Error::InvalidSfdp)
+        ));
+        assert_eq!(flash.timing_profile(), TimingProfile::DATASHEET);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn read_all_images_the_whole_chip() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().set_memory(0, b"first");
+        sim.borrow_mut().set_memory(CAPACITY / 2 - 2, b"middle");
+        sim.borrow_mut().set_memory(CAPACITY - 4, b"last");
+
+        let data = block_on(flash.read_all()).unwrap();
+
+        assert_eq!(data.len(), CAPACITY as usize);
+        assert_eq!(&data[..5], b"first");
+        assert_eq!(&data[CAPACITY as usize / 2 - 2..][..6], b"middle");
+        assert_eq!(&data[CAPACITY as usize - 4..], b"last");
+        assert_eq!(data.iter().filter(|&&byte| byte != 0xFF).count(), 15);
+        assert_eq!(sim.borrow().count(0x13), N_SECTORS as usize);
+    }
+
+    #[test]
+    fn find_write_cursor_in_partially_written_region() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut()
+            .set_memory(0x8000, &[0x00; 3 * PAGE_SIZE as usize + 1]);
+
+        assert_eq!(
+            block_on(flash.find_write_cursor(0x8000, SECTOR_SIZE)).unwrap(),
+            Some(0x8000 + 4 * PAGE_SIZE)
+        );
+        assert_eq!(
+            block_on(flash.find_write_cursor(0x9000, SECTOR_SIZE)).unwrap(),
+            Some(0x9000)
+        );
+    }
+
+    #[test]
+    fn find_write_cursor_in_fully_written_region() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut()
+            .set_memory(0x8000, &[0x00; 2 * PAGE_SIZE as usize]);
+        // A single written byte in the last, partial step
+        sim.borrow_mut()
+            .set_memory(0x8000 + 2 * PAGE_SIZE + 9, &[0x00]);
+
+        assert_eq!(
+            block_on(flash.find_write_cursor(0x8000, 2 * PAGE_SIZE + 10)).unwrap(),
+            None
+        );
+        assert!(block_on(flash.find_write_cursor(CAPACITY - 1, 2))
+            .unwrap_err()
+            .is_out_of_bounds());
+    }
+
+    #[test]
+    fn write_across_16mb() {
+        let (sim, mut flash) = mock::flash();
+        let data: [u8; 2 * PAGE_SIZE as usize + 32] = core::array::from_fn(|i| i as u8);
+
+        block_on(flash.write(0x00FF_FF00, &data)).unwrap();
+
+        let chip = sim.borrow();
+        let programs = chip.commands(0x12);
+        assert_eq!(programs.len(), 3);
+        assert_eq!(programs[0][..5], [0x12, 0x00, 0xFF, 0xFF, 0x00]);
+        assert_eq!(programs[1][..5], [0x12, 0x01, 0x00, 0x00, 0x00]);
+        assert_eq!(programs[2][..5], [0x12, 0x01, 0x00, 0x01, 0x00]);
+        assert_eq!(chip.memory(0x00FF_FF00, data.len()), data);
+    }
+
+    #[test]
+    fn unaligned_write_across_16mb() {
+        let (sim, mut flash) = mock::flash();
+
+        block_on(flash.write(0x00FF_FFF0, &[0x11; 32])).unwrap();
+
+        let chip = sim.borrow();
+        assert_eq!(chip.program_addresses(), [0x00FF_FFF0, 0x0100_0000]);
+        assert_eq!(chip.commands(0x12)[0].len(), 5 + 16);
+        assert_eq!(chip.memory(0x00FF_FFF0, 32), [0x11; 32]);
+        assert_eq!(chip.memory(0x0100_0010, 1), [0xFF]);
+    }
+
+    #[test]
+    fn erase_sector_if_needed_skips_blank_sector() {
+        let (sim, mut flash) = mock::flash();
+
+        assert!(!block_on(flash.erase_sector_if_needed(7)).unwrap());
+        assert_eq!(sim.borrow().count(0x21), 0);
+    }
+
+    #[test]
+    fn erase_sector_if_needed_erases_dirty_sector() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().set_memory(8 * SECTOR_SIZE - 1, &[0xFE]);
+
+        assert!(block_on(flash.erase_sector_if_needed(7)).unwrap());
+        assert_eq!(
+            sim.borrow().commands(0x21),
+            [[0x21, 0x00, 0x00, 0x70, 0x00]]
+        );
+        assert_eq!(sim.borrow().memory(8 * SECTOR_SIZE - 1, 1), [0xFF]);
+
+        assert!(block_on(flash.erase_sector_if_needed(N_SECTORS))
+            .unwrap_err()
+            .is_out_of_bounds());
+    }
+
+    #[test]
+    fn read_scatter_fills_every_buffer() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().set_memory(0x0000, b"boot");
+        sim.borrow_mut().set_memory(0x0002_0000, b"app-a");
+        sim.borrow_mut().set_memory(0x0180_0000, b"app-b!");
+
+        let (mut a, mut b, mut c) = ([0; 4], [0; 5], [0; 6]);
+        block_on(flash.read_scatter(&mut [
+            (0x0000, &mut a),
+            (0x0002_0000, &mut b),
+            (0x0180_0000, &mut c),
+        ]))
+        .unwrap();
+
+        assert_eq!(&a, b"boot");
+        assert_eq!(&b, b"app-a");
+        assert_eq!(&c, b"app-b!");
+    }
+
+    #[test]
+    fn read_scatter_checks_all_ranges_first() {
+        let (sim, mut flash) = mock::flash();
+
+        let (mut a, mut b) = ([0; 4], [0; 4]);
+        assert!(
+            block_on(flash.read_scatter(&mut [(0, &mut a), (CAPACITY - 2, &mut b)]))
+                .unwrap_err()
+                .is_out_of_bounds()
+        );
+        assert_eq!(sim.borrow().transactions(), 0);
+    }
+
+    #[test]
+    fn nor_flash_write_erases_on_demand() {
+        let (sim, mut flash) = mock::flash();
+        flash.set_auto_erase_on_write(true);
+        sim.borrow_mut().set_memory(2 * SECTOR_SIZE, &[0x0F; 16]);
+
+        block_on(NorFlash::write(&mut flash, 2 * SECTOR_SIZE + 4, &[0xF0; 4])).unwrap();
+
+        let chip = sim.borrow();
+        assert_eq!(chip.commands(0x21), [[0x21, 0x00, 0x00, 0x20, 0x00]]);
+        // The rest of the sector is preserved
+        assert_eq!(chip.memory(2 * SECTOR_SIZE, 4), [0x0F; 4]);
+        assert_eq!(chip.memory(2 * SECTOR_SIZE + 4, 4), [0xF0; 4]);
+        assert_eq!(chip.memory(2 * SECTOR_SIZE + 8, 8), [0x0F; 8]);
+    }
+
+    #[test]
+    fn nor_flash_write_skips_erase_when_programmable() {
+        let (sim, mut flash) = mock::flash();
+        flash.set_auto_erase_on_write(true);
+        sim.borrow_mut().set_memory(2 * SECTOR_SIZE, &[0x0F; 16]);
+
+        block_on(NorFlash::write(&mut flash, 2 * SECTOR_SIZE + 4, &[0x05; 4])).unwrap();
+
+        assert_eq!(sim.borrow().count(0x21), 0);
+        assert_eq!(sim.borrow().memory(2 * SECTOR_SIZE + 4, 4), [0x05; 4]);
+    }
+
+    #[test]
+    fn nor_flash_write_keeps_the_nor_contract_by_default() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().set_memory(2 * SECTOR_SIZE, &[0x0F; 16]);
+
+        let result = block_on(NorFlash::write(&mut flash, 2 * SECTOR_SIZE + 4, &[0xF0; 4]));
+
+        assert_eq!(sim.borrow().count(0x21), 0);
+        // The data is ANDed with the old contents
+        assert_eq!(sim.borrow().memory(2 * SECTOR_SIZE + 4, 4), [0x00; 4]);
+        assert_eq!(result.is_err(), cfg!(feature = "verify-writes"));
+    }
+
+    #[test]
+    #[cfg(feature = "stats")]
+    fn stats_count_programmed_and_erased_bytes() {
+        let (_, mut flash) = mock::flash();
+
+        block_on(flash.write(0x80, &[0; 300])).unwrap();
+        block_on(flash.write(0x1000, &[0; 10])).unwrap();
+        block_on(flash.erase_sector(4)).unwrap();
+        block_on(flash.erase_block_64k(1)).unwrap();
+
+        assert_eq!(
+            flash.stats(),
+            Stats {
+                total_bytes_programmed: 310,
+                total_bytes_erased: (SECTOR_SIZE + BLOCK_64K_SIZE) as u64,
+            }
+        );
+
+        flash.reset_stats();
+        assert_eq!(flash.stats(), Stats::default());
+    }
+
+    #[test]
+    fn enter_4_byte_address_mode_is_verified() {
+        let (sim, mut flash) = mock::flash();
+
+        block_on(flash.enter_4_byte_address_mode()).unwrap();
+        assert_eq!(sim.borrow().opcodes(), [0xB7, 0x15]);
+        assert!(block_on(flash.in_4_byte_address_mode()).unwrap());
+
+        block_on(flash.exit_4_byte_address_mode()).unwrap();
+        assert!(!block_on(flash.in_4_byte_address_mode()).unwrap());
+    }
+
+    #[test]
+    fn enter_4_byte_address_mode_fails_if_ads_stays_clear() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().ignore_address_mode = true;
+
+        assert!(matches!(
+            block_on(flash.enter_4_byte_address_mode()),
+            Err(Error::AddressModeFail)
+        ));
+    }
+
+    #[test]
+    fn read_vendor_sfdp_table_follows_the_parameter_header() {
+        let (sim, mut flash) = mock::flash();
+        let mut sfdp = std::vec![0xFF; 0x100];
+        // Two parameter headers: the basic table and a Winbond table of 2 DWORDs at 0xD0
+        sfdp[..8].copy_from_slice(&[b'S', b'F', b'D', b'P', 0x06, 0x01, 0x01, 0xFF]);
+        sfdp[8..16].copy_from_slice(&[0x00, 0x06, 0x01, 16, 0x80, 0x00, 0x00, 0xFF]);
+        sfdp[16..24].copy_from_slice(&[0xEF, 0x00, 0x01, 2, 0xD0, 0x00, 0x00, 0xFF]);
+        sfdp[0xD0..0xD8].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        sim.borrow_mut().sfdp = sfdp;
+
+        let mut buf = [0; 16];
+        assert_eq!(
+            block_on(flash.read_vendor_sfdp_table(&mut buf)).unwrap(),
+            Some(8)
+        );
+        assert_eq!(buf[..8], [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(
+            sim.borrow().commands(0x5A).last().unwrap(),
+            &[0x5A, 0x00, 0x00, 0xD0, 0x00]
+        );
+    }
+
+    #[test]
+    fn read_vendor_sfdp_table_without_vendor_table() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().sfdp = sfdp_with_timing();
+
+        assert_eq!(
+            block_on(flash.read_vendor_sfdp_table(&mut [0; 16])).unwrap(),
+            None
+        );
+
+        sim.borrow_mut().sfdp.clear();
+        assert!(matches!(
+            block_on(flash.read_vendor_sfdp_table(&mut [0; 16])),
+            Err(Error::InvalidSfdp)
+        ));
+    }
+
+    /// Starts a sector erase and drops its future while the chip is still busy with it.
+    fn drop_erase_midway(sim: &mock::Sim, flash: &mut mock::Flash<mock::Delay>) {
+        sim.borrow_mut().busy_polls = 100;
+        let poll = embassy_futures::poll_once(flash.erase_sector(0));
+        assert!(poll.is_pending());
+        assert!(sim.borrow().is_busy());
+        sim.borrow_mut().log.clear();
+    }
+
+    #[test]
+    fn overlapping_operations_return_busy() {
+        let (sim, mut flash) = mock::flash_with_delay(0);
+        drop_erase_midway(&sim, &mut flash);
+
+        assert!(matches!(
+            block_on(flash.write(0x1000, &[0; 4])),
+            Err(Error::Busy)
+        ));
+        assert!(matches!(
+            block_on(flash.write_counted(0x1000, &[0; 4])),
+            Err((0, Error::Busy))
+        ));
+        assert!(matches!(block_on(flash.erase_sector(1)), Err(Error::Busy)));
+        assert!(matches!(
+            block_on(flash.erase_block_32k(1)),
+            Err(Error::Busy)
+        ));
+        assert!(matches!(
+            block_on(flash.erase_block_64k(1)),
+            Err(Error::Busy)
+        ));
+        assert!(matches!(block_on(flash.erase_chip()), Err(Error::Busy)));
+        assert!(matches!(
+            block_on(flash.write_status_register_1(0)),
+            Err(Error::Busy)
+        ));
+        assert!(matches!(
+            block_on(flash.set_hold_reset_mode(HoldResetMode::Hold)),
+            Err(Error::Busy)
+        ));
+        let mut delay = mock::Delay(sim.clone());
+        assert!(matches!(
+            block_on(flash.power_down(&mut delay)),
+            Err(Error::Busy)
+        ));
+
+        // Nothing but status register reads reached the chip
+        let chip = sim.borrow();
+        assert!(chip
+            .opcodes()
+            .iter()
+            .all(|opcode| [0x05, 0x35, 0x15].contains(opcode)));
+        assert!(chip.is_busy());
+    }
+
+    #[test]
+    fn operation_after_dropped_one_completed() {
+        let (sim, mut flash) = mock::flash_with_delay(0);
+        drop_erase_midway(&sim, &mut flash);
+        sim.borrow_mut().set_busy(0);
+
+        block_on(flash.write(0x1000, &[0; 4])).unwrap();
+        assert_eq!(sim.borrow().memory(0x1000, 4), [0; 4]);
+    }
+
+    #[test]
+    fn remaining_erase_cycles_decrease_after_erases() {
+        let (sim, mut flash) = mock::flash();
+        let mut remaining = remaining_erase_cycles(sim.borrow().count(0x21) as u32);
+        assert_eq!(remaining, ERASE_CYCLES);
+
+        for _ in 0..3 {
+            block_on(flash.erase_sector(5)).unwrap();
+
+            let after = remaining_erase_cycles(sim.borrow().count(0x21) as u32);
+            assert_eq!(after, remaining - 1);
+            remaining = after;
+        }
+        assert_eq!(remaining, ERASE_CYCLES - 3);
+
+        assert_eq!(remaining_erase_cycles(ERASE_CYCLES + 1), 0);
+    }
+
+    #[test]
+    fn hardware_protect_sets_srp_then_asserts_wp() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().status_registers[0] = 0x1C;
+
+        block_on(flash.hardware_protect_status_register()).unwrap();
+        assert_eq!(sim.borrow().status_registers[0], 0x9C);
+        {
+            let chip = sim.borrow();
+            let write = chip
+                .log
+                .iter()
+                .position(|event| matches!(event, mock::Event::Transaction { tx, .. } if tx.starts_with(&[0x01, 0x9C])))
+                .expect("SRP is written with the other bits kept");
+            let wp = chip
+                .log
+                .iter()
+                .position(|event| matches!(event, mock::Event::Wp(PinState::Low)))
+                .expect("WP is asserted");
+            assert!(write < wp, "WP is asserted only after SRP is set");
+        }
+
+        sim.borrow_mut().log.clear();
+        block_on(flash.hardware_unprotect_status_register()).unwrap();
+        assert_eq!(sim.borrow().status_registers[0], 0x1C);
+        let chip = sim.borrow();
+        assert!(matches!(chip.log[0], mock::Event::Wp(PinState::High)));
+        assert!(chip.commands(0x01)[0].starts_with(&[0x01, 0x1C]));
+    }
+
+    #[cfg(feature = "embassy")]
+    #[test]
+    fn read_yielding_yields_after_every_chunk() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().set_memory(0x2_0000, &[0x5A; 1000]);
+
+        let mut buf = [0; 1000];
+        let mut yields = 0;
+        {
+            let mut read = core::pin::pin!(flash.read_yielding(0x2_0000, &mut buf, 256));
+            while embassy_futures::poll_once(read.as_mut()).is_pending() {
+                yields += 1;
+            }
+        }
+
+        // Four chunks, the last one 232 bytes long
+        assert_eq!(yields, 4);
+        assert_eq!(sim.borrow().count(0x13), 4);
+        assert_eq!(buf, [0x5A; 1000]);
+    }
+
+    #[test]
+    fn verify_addressing_round_trips_marker_above_16mb() {
+        let (sim, mut flash) = mock::flash();
+        let address = CAPACITY - SECTOR_SIZE;
+        sim.borrow_mut()
+            .set_memory(address - 0x0100_0000, b"lowerhalf");
+
+        assert!(block_on(flash.verify_addressing()).unwrap());
+
+        let chip = sim.borrow();
+        assert_eq!(chip.memory(address, 8), b"W25Q4BYT");
+        assert_eq!(chip.memory(address - 0x0100_0000, 9), b"lowerhalf");
+        assert!(chip.program_addresses().iter().all(|&a| a >= 0x0100_0000));
+    }
+
+    #[test]
+    fn verify_addressing_detects_bad_marker() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut()
+            .stuck_bits
+            .push((CAPACITY - SECTOR_SIZE + 3, 0x01, 0x00));
+
+        match block_on(flash.verify_addressing()) {
+            Err(Error::ReadbackFail) if cfg!(feature = "verify-writes") => {}
+            result => assert!(!result.unwrap()),
+        }
+    }
+
+    #[test]
+    fn security_lock_bits_decode_each_lb_bit() {
+        let (sim, mut flash) = mock::flash();
+
+        for (sr2, expected) in [
+            (0x00, [false, false, false]),
+            (0x08, [true, false, false]),
+            (0x10, [false, true, false]),
+            (0x20, [false, false, true]),
+            (0x3A, [true, true, true]),
+        ] {
+            sim.borrow_mut().status_registers[1] = sr2;
+            assert_eq!(block_on(flash.security_lock_bits()).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn erase_chip_sampled_verify_checks_the_chosen_sectors() {
+        let (sim, mut flash) = mock::flash();
+        let mut values = [7, N_SECTORS + 2, 4000].into_iter();
+
+        block_on(flash.erase_chip_sampled_verify(3, &mut || values.next().unwrap())).unwrap();
+
+        // The blank checks are the last reads, after any verification of the erase itself
+        let reads = 3 * (SECTOR_SIZE / PAGE_SIZE) as usize * READS_PER_PAGE;
+        let checked: std::collections::BTreeSet<u32> = sim
+            .borrow()
+            .read_addresses()
+            .iter()
+            .rev()
+            .take(reads)
+            .map(|address| address / SECTOR_SIZE)
+            .collect();
+        assert_eq!(
+            checked.into_iter().collect::<std::vec::Vec<_>>(),
+            [2, 7, 4000]
+        );
+        assert_eq!(sim.borrow().count(0xC7) + sim.borrow().count(0x60), 1);
+    }
+
+    #[test]
+    fn erase_chip_sampled_verify_catches_a_sector_that_is_not_blank() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut()
+            .stuck_bits
+            .push((9 * SECTOR_SIZE + 100, 0x10, 0x00));
+
+        let mut values = [3, 9, 12].into_iter();
+        assert!(matches!(
+            block_on(flash.erase_chip_sampled_verify(3, &mut || values.next().unwrap())),
+            Err(Error::ReadbackFail)
+        ));
+        // Sector 12 isn't checked anymore
+        assert_eq!(values.next(), Some(12));
+    }
+
+    #[test]
+    fn write_readiness_decodes_status_registers_1_and_2() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().status_registers[0] = 0x5C;
+        sim.borrow_mut().status_registers[1] = 0x40;
+        block_on(flash.enable_write()).unwrap();
+        sim.borrow_mut().set_busy(2);
+        sim.borrow_mut().log.clear();
+
+        assert_eq!(
+            block_on(flash.write_readiness()).unwrap(),
+            WriteReadiness {
+                busy: true,
+                write_enabled: true,
+                protected_region: BlockProtection {
+                    bp: 0x07,
+                    from_bottom: true,
+                    complement: true,
+                },
+            }
+        );
+        assert_eq!(sim.borrow().frames(), [[0x05, 0x00], [0x35, 0x00]]);
+    }
+
+    #[cfg(feature = "defmt")]
+    #[test]
+    fn log_duration_measures_with_the_clock() {
+        use core::sync::atomic::{AtomicU32, Ordering};
+
+        static NOW: AtomicU32 = AtomicU32::new(0);
+        // Every reading of the clock advances it by 10 ticks
+        fn clock() -> u32 {
+            NOW.fetch_add(10, Ordering::Relaxed) + 10
+        }
+
+        let (sim, mut flash) = mock::flash();
+        flash.set_clock(clock);
+
+        NOW.store(1000, Ordering::Relaxed);
+        assert_eq!(flash.log_duration(990, 400), 20);
+        // The clock is allowed to wrap around
+        NOW.store(5, Ordering::Relaxed);
+        assert_eq!(flash.log_duration(u32::MAX - 4, 400), 20);
+
+        // Each operation reads the clock once at its start and once at its end
+        NOW.store(0, Ordering::Relaxed);
+        sim.borrow_mut().busy_polls = 3;
+        block_on(flash.erase_sector(1)).unwrap();
+        assert!(NOW.load(Ordering::Relaxed) >= 20);
+    }
+
+    #[test]
+    fn write_token_round_trip() {
+        let (_, mut flash) = mock::flash();
+        let data = *b"deferred verification";
+
+        let token = block_on(flash.write_token(0x3100, &data)).unwrap();
+        assert_eq!(
+            token,
+            WriteToken {
+                address: 0x3100,
+                len: data.len() as u32,
+                crc: crc32::Crc32::checksum(&data),
+            }
+        );
+        assert!(block_on(flash.verify_token(&token)).unwrap());
+    }
+
+    #[test]
+    fn verify_token_detects_corruption_after_the_write() {
+        let (sim, mut flash) = mock::flash();
+
+        let token = block_on(flash.write_token(0x3100, b"deferred verification")).unwrap();
+        sim.borrow_mut().set_memory(0x3100 + 9, b"V");
+
+        assert!(!block_on(flash.verify_token(&token)).unwrap());
+    }
+
+    #[test]
+    fn erase_range_cancellable_stops_after_the_current_sector() {
+        let (sim, mut flash) = mock::flash_with_delay(0);
+        sim.borrow_mut().busy_polls = 3;
+        sim.borrow_mut()
+            .set_memory(4 * SECTOR_SIZE, &[0x00; 4 * SECTOR_SIZE as usize]);
+        let cancel = core::sync::atomic::AtomicBool::new(false);
+
+        let mut erase = core::pin::pin!(flash.erase_range_cancellable(
+            4 * SECTOR_SIZE,
+            8 * SECTOR_SIZE,
+            &cancel
+        ));
+        while sim.borrow().count(0x21) == 0 {
+            assert!(embassy_futures::poll_once(erase.as_mut()).is_pending());
+        }
+        // Cancelled while the first sector is being erased
+        assert!(sim.borrow().is_busy());
+        cancel.store(true, core::sync::atomic::Ordering::Relaxed);
+
+        assert!(matches!(block_on(erase), Err(Error::Cancelled)));
+        let chip = sim.borrow();
+        assert!(!chip.is_busy());
+        assert_eq!(chip.count(0x21), 1);
+        assert_eq!(
+            chip.memory(4 * SECTOR_SIZE, SECTOR_SIZE as usize),
+            [0xFF; SECTOR_SIZE as usize]
+        );
+        assert_eq!(chip.memory(5 * SECTOR_SIZE, 4), [0x00; 4]);
+    }
+
+    #[test]
+    fn read_page_reads_the_whole_page_at_its_index() {
+        let (sim, mut flash) = mock::flash();
+        let page: std::vec::Vec<u8> = (0..PAGE_SIZE).map(|i| i as u8).collect();
+        sim.borrow_mut().set_memory(1234 * PAGE_SIZE, &page);
+
+        let mut buf = [0; PAGE_SIZE as usize];
+        block_on(flash.read_page(1234, &mut buf)).unwrap();
+
+        assert_eq!(buf[..], page[..]);
+        let chip = sim.borrow();
+        assert_eq!(chip.read_addresses(), [1234 * PAGE_SIZE]);
+        assert!(matches!(
+            chip.log[..],
+            [mock::Event::Transaction { read_len, .. }] if read_len == PAGE_SIZE as usize
+        ));
+    }
+
+    #[test]
+    fn read_page_rejects_index_past_the_last_page() {
+        let (sim, mut flash) = mock::flash();
+        let mut buf = [0; PAGE_SIZE as usize];
+
+        block_on(flash.read_page(N_PAGES - 1, &mut buf)).unwrap();
+        assert!(matches!(
+            block_on(flash.read_page(N_PAGES, &mut buf)),
+            Err(Error::OutOfBounds)
+        ));
+        assert_eq!(sim.borrow().count(0x13), 1);
+    }
+
+    #[test]
+    fn write_status_register_1_preserves_quad_enable() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().status_registers[1] = 0x02;
+
+        block_on(flash.write_status_register_1(0x1C)).unwrap();
+
+        let chip = sim.borrow();
+        assert_eq!(chip.status_registers[..2], [0x1C, 0x02]);
+        assert_eq!(chip.commands(0x01), [[0x01, 0x1C, 0x02]]);
+    }
+
+    #[test]
+    fn program_region_preserves_the_rest_of_the_sector() {
+        let (sim, mut flash) = mock::flash();
+        let sector = 7 * SECTOR_SIZE;
+        let contents: std::vec::Vec<u8> = (0..SECTOR_SIZE).map(|i| (i % 251) as u8).collect();
+        sim.borrow_mut().set_memory(sector, &contents);
+
+        let mut scratch = [0; SECTOR_SIZE as usize];
+        block_on(flash.program_region(sector + 1000, &[0xA5; 100], &mut scratch)).unwrap();
+
+        let mut expected = contents.clone();
+        expected[1000..1100].fill(0xA5);
+        let chip = sim.borrow();
+        assert_eq!(chip.memory(sector, SECTOR_SIZE as usize), expected);
+        assert_eq!(chip.commands(0x21), [[0x21, 0x00, 0x00, 0x70, 0x00]]);
+    }
+
+    #[test]
+    fn program_region_skips_the_erase_of_erased_bytes() {
+        let (sim, mut flash) = mock::flash();
+
+        let mut scratch = [0; SECTOR_SIZE as usize];
+        block_on(flash.program_region(7 * SECTOR_SIZE + 1000, &[0xA5; 100], &mut scratch)).unwrap();
+
+        assert_eq!(sim.borrow().count(0x21), 0);
+        assert_eq!(
+            sim.borrow().memory(7 * SECTOR_SIZE + 1000, 100),
+            [0xA5; 100]
+        );
+    }
+
+    /// Stores a copy of the data followed by its checksum.
+    fn store_copy(sim: &mock::Sim, address: u32, data: &[u8]) {
+        let mut chip = sim.borrow_mut();
+        chip.set_memory(address, data);
+        chip.set_memory(
+            address + data.len() as u32,
+            &crc32::Crc32::checksum(data).to_le_bytes(),
+        );
+    }
+
+    #[test]
+    fn read_redundant_uses_the_valid_first_copy() {
+        let (sim, mut flash) = mock::flash();
+        store_copy(&sim, 0x1000, b"config a");
+        store_copy(&sim, 0x2000, b"config b");
+
+        let mut buf = [0; 8];
+        assert!(!block_on(flash.read_redundant(0x1000, 0x2000, &mut buf)).unwrap());
+        assert_eq!(&buf, b"config a");
+        assert!(sim.borrow().read_addresses().iter().all(|&a| a < 0x2000));
+    }
+
+    #[test]
+    fn read_redundant_falls_back_to_the_second_copy() {
+        let (sim, mut flash) = mock::flash();
+        store_copy(&sim, 0x1000, b"config a");
+        store_copy(&sim, 0x2000, b"config b");
+        sim.borrow_mut().set_memory(0x1003, b"F");
+
+        let mut buf = [0; 8];
+        assert!(block_on(flash.read_redundant(0x1000, 0x2000, &mut buf)).unwrap());
+        assert_eq!(&buf, b"config b");
+    }
+
+    #[test]
+    fn read_redundant_fails_if_both_copies_are_corrupt() {
+        let (sim, mut flash) = mock::flash();
+        store_copy(&sim, 0x1000, b"config a");
+        store_copy(&sim, 0x2000, b"config b");
+        sim.borrow_mut().set_memory(0x1003, b"F");
+        sim.borrow_mut().set_memory(0x2008, &[0; 4]);
+
+        let mut buf = [0; 8];
+        assert!(matches!(
+            block_on(flash.read_redundant(0x1000, 0x2000, &mut buf)),
+            Err(Error::ReadbackFail)
+        ));
+    }
+
+    #[test]
+    fn release_bus_restores_factory_default_mode() {
+        let (sim, mut flash) = mock::flash();
+        block_on(flash.enter_4_byte_address_mode()).unwrap();
+        sim.borrow_mut().status_registers[1] = 0x02;
+        sim.borrow_mut().log.clear();
+
+        block_on(flash.release_bus(true)).unwrap();
+
+        let chip = sim.borrow();
+        let opcodes: std::vec::Vec<u8> = chip
+            .opcodes()
+            .into_iter()
+            .filter(|&opcode| opcode != 0x05)
+            .collect();
+        assert_eq!(opcodes, [0x04, 0x15, 0xE9, 0x35, 0x06, 0x31]);
+        assert_eq!(chip.status_registers[1] & 0x02, 0);
+        assert_eq!(chip.status_registers[2] & 0x01, 0);
+        assert_eq!(
+            chip.log[chip.log.len() - 2..],
+            [
+                mock::Event::Hold(PinState::High),
+                mock::Event::Wp(PinState::High)
+            ]
+        );
+    }
+
+    #[test]
+    fn release_bus_leaves_an_undriven_wp_pin_alone() {
+        let sim = mock::Sim::default();
+        let mut flash = W25q256jv::new_no_wp_drive(
+            mock::Spi(sim.clone()),
+            mock::Pin::hold(&sim),
+            mock::Pin::wp(&sim),
+        )
+        .unwrap();
+        sim.borrow_mut().log.clear();
+
+        block_on(flash.release_bus(false)).unwrap();
+
+        let chip = sim.borrow();
+        assert_eq!(chip.opcodes(), [0x05, 0x04, 0x15]);
+        assert_eq!(chip.log.last(), Some(&mock::Event::Hold(PinState::High)));
+        assert!(!chip
+            .log
+            .iter()
+            .any(|event| matches!(event, mock::Event::Wp(_))));
+    }
+
+    #[test]
+    fn device_fingerprint_is_deterministic() {
+        let (sim, mut flash) = mock::flash();
+
+        let fingerprint = block_on(flash.device_fingerprint()).unwrap();
+        // 128-bit FNV-1a of the unique ID 0123456789ABCDEF followed by the JEDEC ID EF4019
+        assert_eq!(
+            fingerprint,
+            0x75132ed196bbd139859a0bf4db7825e9_u128.to_be_bytes()
+        );
+        assert_eq!(block_on(flash.device_fingerprint()).unwrap(), fingerprint);
+
+        sim.borrow_mut().unique_id[7] = 0xEE;
+        assert_ne!(block_on(flash.device_fingerprint()).unwrap(), fingerprint);
+    }
+
+    /// Returns the opcode of the transaction following every Write Enable.
+    fn after_write_enable(sim: &mock::Sim) -> std::vec::Vec<u8> {
+        let opcodes = sim.borrow().opcodes();
+        opcodes
+            .windows(2)
+            .filter(|pair| pair[0] == 0x06)
+            .map(|pair| pair[1])
+            .collect()
+    }
+
+    #[test]
+    fn write_confirms_write_enable_by_default() {
+        let (sim, mut flash) = mock::flash();
+
+        block_on(flash.write(0x1000, &[0x12; 300])).unwrap();
+        assert_eq!(after_write_enable(&sim), [0x05, 0x05]);
+    }
+
+    #[test]
+    fn fast_write_skips_the_write_enable_confirmation() {
+        let (sim, mut flash) = mock::flash();
+        flash.set_fast_write(true);
+
+        block_on(flash.write(0x1000, &[0x12; 300])).unwrap();
+        assert_eq!(after_write_enable(&sim), [0x12, 0x12]);
+        assert_eq!(sim.borrow().memory(0x1000, 300), [0x12; 300]);
+    }
+
+    #[test]
+    fn transactions_for_write_matches_a_cross_page_write() {
+        let (sim, mut flash) = mock::flash();
+        // Every operation completes on the first status poll
+        sim.borrow_mut().busy_polls = 0;
+
+        block_on(flash.write(0x10F0, &[0x12; 300])).unwrap();
+
+        assert_eq!(
+            sim.borrow().transactions(),
+            transactions_for_write(0x10F0, 300)
+        );
+        // 16, 256 and 28 bytes in three pages, each read back in chunks of 64 bytes
+        let readback = if cfg!(feature = "verify-writes") {
+            1 + 4 + 1
+        } else {
+            0
+        };
+        assert_eq!(transactions_for_write(0x10F0, 300), 1 + 3 * 4 + readback);
+    }
+
+    #[test]
+    fn transactions_for_erase_range_matches_a_multi_sector_erase() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().busy_polls = 0;
+
+        block_on(flash.erase_range(3 * SECTOR_SIZE, 6 * SECTOR_SIZE)).unwrap();
+
+        assert_eq!(
+            sim.borrow().transactions(),
+            transactions_for_erase_range(3 * SECTOR_SIZE, 6 * SECTOR_SIZE)
+        );
+    }
+
+    #[test]
+    fn transactions_for_erase_range_is_one_per_sector_less_with_fast_write() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().busy_polls = 0;
+        flash.set_fast_write(true);
+
+        block_on(flash.erase_range(3 * SECTOR_SIZE, 6 * SECTOR_SIZE)).unwrap();
+
+        assert_eq!(
+            sim.borrow().transactions(),
+            transactions_for_erase_range(3 * SECTOR_SIZE, 6 * SECTOR_SIZE) - 3
+        );
+    }
+
+    #[test]
+    fn set_complement_protect_changes_only_cmp() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().status_registers[0] = 0x24;
+        sim.borrow_mut().status_registers[1] = 0x02;
+
+        block_on(flash.set_complement_protect(true)).unwrap();
+        assert_eq!(sim.borrow().status_registers[1], 0x42);
+        assert_eq!(sim.borrow().commands(0x31), [[0x31, 0x42]]);
+        assert!(block_on(flash.complement_protect()).unwrap());
+
+        // BP=1001 protects the upper 16MB, CMP inverts it to the lower 16MB
+        let protection = block_on(flash.write_readiness()).unwrap().protected_region;
+        assert!(protection.complement);
+        assert_eq!(protection.protected_range(), 0..0x0100_0000);
+
+        block_on(flash.set_complement_protect(false)).unwrap();
+        assert_eq!(sim.borrow().status_registers[1], 0x02);
+        let protection = block_on(flash.write_readiness()).unwrap().protected_region;
+        assert_eq!(protection.protected_range(), 0x0100_0000..CAPACITY);
+    }
+
+    #[test]
+    fn write_with_diff_reports_the_first_flipped_byte() {
+        let (sim, mut flash) = mock::flash();
+        let data: std::vec::Vec<u8> = (0..100).collect();
+        sim.borrow_mut().read_faults.push(mock::ReadFault {
+            address: 0x5000 + 70,
+            xor: 0x81,
+            opcode: Some(0x13),
+            remaining: usize::MAX,
+        });
+
+        let mut scratch = [0; 32];
+        match block_on(flash.write_with_diff(0x5000, &data, &mut scratch)) {
+            // The write reads back the data itself and notices the mismatch first
+            Err(Error::ReadbackFail) if cfg!(feature = "verify-writes") => {}
+            result => assert_eq!(result.unwrap(), Some((0x5000 + 70, 70, 70 ^ 0x81))),
+        }
+    }
+
+    #[test]
+    fn write_with_diff_returns_none_if_all_bytes_match() {
+        let (_, mut flash) = mock::flash();
+        let data: std::vec::Vec<u8> = (0..100).collect();
+
+        let mut scratch = [0; 32];
+        assert_eq!(
+            block_on(flash.write_with_diff(0x5000, &data, &mut scratch)).unwrap(),
+            None
+        );
+        assert!(matches!(
+            block_on(flash.write_with_diff(0x5000, &data, &mut [])),
+            Err(Error::BufferTooSmall)
+        ));
+    }
+
+    #[test]
+    fn march_test_sector_passes_on_a_good_sector() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut()
+            .set_memory(3 * SECTOR_SIZE, &[0x12; SECTOR_SIZE as usize]);
+
+        assert!(block_on(flash.march_test_sector(3)).unwrap());
+
+        let chip = sim.borrow();
+        // Erased before every pattern and at the end
+        assert_eq!(chip.count(0x21), 4);
+        assert_eq!(
+            chip.memory(3 * SECTOR_SIZE, SECTOR_SIZE as usize),
+            [0xFF; SECTOR_SIZE as usize]
+        );
+    }
+
+    #[test]
+    fn march_test_sector_fails_on_a_stuck_bit() {
+        for value in [0x00, 0x04] {
+            let (sim, mut flash) = mock::flash();
+            sim.borrow_mut()
+                .stuck_bits
+                .push((3 * SECTOR_SIZE + 1234, 0x04, value));
+
+            assert!(!block_on(flash.march_test_sector(3)).unwrap());
+        }
+    }
+
+    #[test]
+    fn cross_read_verify_uses_both_read_commands() {
+        let (sim, mut flash) = mock::flash();
+        let data: std::vec::Vec<u8> = (0..100).collect();
+
+        block_on(flash.cross_read_verify(0x6000, &data)).unwrap();
+
+        let chip = sim.borrow();
+        assert_eq!(
+            chip.commands(0x0C),
+            [
+                [0x0C, 0x00, 0x00, 0x60, 0x00, 0x00],
+                [0x0C, 0x00, 0x00, 0x60, 0x40, 0x00]
+            ]
+        );
+        let reads = if cfg!(feature = "verify-writes") {
+            4
+        } else {
+            2
+        };
+        assert_eq!(chip.count(0x13), reads);
+    }
+
+    #[test]
+    fn cross_read_verify_detects_a_fast_read_mismatch() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().read_faults.push(mock::ReadFault {
+            address: 0x6000 + 80,
+            xor: 0x01,
+            opcode: Some(0x0C),
+            remaining: 1,
+        });
+
+        assert!(matches!(
+            block_on(flash.cross_read_verify(0x6000, &[0x33; 100])),
+            Err(Error::ReadbackFail)
+        ));
+        // Only the fast read was disturbed, the programmed data is intact
+        assert_eq!(sim.borrow().memory(0x6000, 100), [0x33; 100]);
+    }
+
+    #[test]
+    fn wake_and_identify_releases_power_down_and_reads_the_device_id() {
+        let (sim, mut flash) = mock::flash();
+        let mut delay = mock::Delay(sim.clone());
+        block_on(flash.power_down(&mut delay)).unwrap();
+        assert!(sim.borrow().powered_down);
+        sim.borrow_mut().log.clear();
+
+        assert_eq!(
+            block_on(flash.wake_and_identify(&mut delay)).unwrap(),
+            DEVICE_ID
+        );
+
+        let chip = sim.borrow();
+        assert!(!chip.powered_down);
+        // The opcode, 3 dummy bytes and the clocked-in device ID, then tRES2
+        assert_eq!(chip.frames(), [[0xAB, 0x00, 0x00, 0x00, 0x00]]);
+        assert_eq!(chip.delays(), [3_000]);
+    }
+
+    #[test]
+    fn write_longer_than_the_limit_is_rejected() {
+        let (sim, mut flash) = mock::flash();
+        flash.set_max_write_len(16);
+
+        block_on(flash.write(0x1000, &[0x11; 16])).unwrap();
+        sim.borrow_mut().log.clear();
+
+        assert!(matches!(
+            block_on(flash.write(0x2000, &[0x22; 17])),
+            Err(Error::WriteTooLarge)
+        ));
+        // Nothing was written
+        assert!(sim.borrow().log.is_empty());
+        assert_eq!(sim.borrow().memory(0x2000, 1), [0xFF]);
+    }
+
+    /// Restores the power after a simulated power loss, and creates a new driver as a restarted MCU would.
+    fn restart(sim: &mock::Sim) -> mock::Flash {
+        {
+            let mut chip = sim.borrow_mut();
+            chip.power_lost = false;
+            chip.set_busy(0);
+        }
+
+        W25q256jv::new(
+            mock::Spi(sim.clone()),
+            mock::Pin::hold(sim),
+            mock::Pin::wp(sim),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn shadow_commit_rejects_a_single_sector() {
+        let (sim, mut flash) = mock::flash();
+
+        assert!(matches!(
+            block_on(flash.shadow_commit(10, 10, b"config")),
+            Err(Error::OutOfBounds)
+        ));
+        assert!(sim.borrow().log.is_empty());
+    }
+
+    #[test]
+    fn shadow_commit_survives_power_loss_after_the_shadow_write() {
+        let (sim, mut flash) = mock::flash();
+        block_on(flash.shadow_commit(10, 11, b"old config")).unwrap();
+
+        // Power is lost right after the primary sector was erased, the second erase of the update
+        sim.borrow_mut().power_loss_after = Some((0x21, 1));
+        assert!(block_on(flash.shadow_commit(10, 11, b"new config")).is_err());
+        assert_eq!(sim.borrow().memory(10 * SECTOR_SIZE, 4), [0xFF; 4]);
+
+        let mut flash = restart(&sim);
+        let mut buf = [0; 10];
+        assert!(block_on(flash.read_committed(10, 11, &mut buf)).unwrap());
+        assert_eq!(&buf, b"new config");
+    }
+
+    #[test]
+    fn shadow_commit_survives_power_loss_during_the_shadow_write() {
+        let (sim, mut flash) = mock::flash();
+        block_on(flash.shadow_commit(10, 11, b"old config")).unwrap();
+
+        // Power is lost after the data was programmed to the shadow sector, before its commit marker
+        sim.borrow_mut().power_loss_after = Some((0x12, 0));
+        assert!(block_on(flash.shadow_commit(10, 11, b"new config")).is_err());
+
+        let mut flash = restart(&sim);
+        let mut buf = [0; 10];
+        assert!(!block_on(flash.read_committed(10, 11, &mut buf)).unwrap());
+        assert_eq!(&buf, b"old config");
+    }
+
+    #[cfg(feature = "diagnostics")]
+    #[test]
+    fn diagnostic_snapshot_gathers_ids_and_registers() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().status_registers = [0x18, 0x42, 0x61];
+
+        let snapshot = block_on(flash.diagnostic_snapshot()).unwrap();
+        assert_eq!(
+            snapshot,
+            Snapshot {
+                jedec_id: JEDEC_ID,
+                unique_id: [0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF],
+                status_registers: [0x18, 0x42, 0x61],
+                four_byte_address_mode: true,
+                quad_enabled: true,
+                protection: BlockProtection {
+                    bp: 0x06,
+                    from_bottom: false,
+                    complement: true,
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn write_counted_reports_the_pages_before_a_failure() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().fail_transaction = Some((0x12, 2));
+
+        assert!(matches!(
+            block_on(flash.write_counted(0x4000, &[0x44; 4 * PAGE_SIZE as usize])),
+            Err((n, Error::SpiError(_))) if n == 2 * PAGE_SIZE as usize
+        ));
+        let chip = sim.borrow();
+        assert_eq!(
+            chip.memory(0x4000, 2 * PAGE_SIZE as usize),
+            [0x44; 2 * PAGE_SIZE as usize]
+        );
+        assert_eq!(chip.memory(0x4000 + 2 * PAGE_SIZE, 1), [0xFF]);
+    }
+
+    #[test]
+    fn write_counted_returns_the_length_on_success() {
+        let (_, mut flash) = mock::flash();
+
+        assert_eq!(
+            block_on(flash.write_counted(0x40F0, &[0x44; 300])).unwrap(),
+            300
+        );
+    }
+
+    #[test]
+    fn read_otp_serial_parses_ascii() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().security_registers[1][..12].copy_from_slice(b"SN-000123456");
+
+        let mut buf = [0; 32];
+        assert_eq!(
+            block_on(flash.read_otp_serial(2, &mut buf)).unwrap(),
+            "SN-000123456"
+        );
+        assert_eq!(
+            sim.borrow().commands(0x48)[0][..4],
+            [0x48, 0x00, 0x20, 0x00]
+        );
+    }
+
+    #[test]
+    fn read_otp_serial_rejects_invalid_utf8() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().security_registers[0][..4].copy_from_slice(&[b'S', b'N', 0xC3, b'-']);
+
+        let mut buf = [0; 32];
+        assert!(matches!(
+            block_on(flash.read_otp_serial(1, &mut buf)),
+            Err(Error::InvalidUtf8)
+        ));
+    }
+
+    #[test]
+    fn replace_sector_saves_a_status_poll_per_page() {
+        let data: std::vec::Vec<u8> = (0..SECTOR_SIZE).map(|i| (i * 3) as u8).collect();
+        let data: &[u8; SECTOR_SIZE as usize] = data[..].try_into().unwrap();
+
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().busy_polls = 0;
+        sim.borrow_mut()
+            .set_memory(2 * SECTOR_SIZE, &[0x00; SECTOR_SIZE as usize]);
+        block_on(flash.replace_sector(2, data)).unwrap();
+
+        let (naive_sim, mut naive) = mock::flash();
+        naive_sim.borrow_mut().busy_polls = 0;
+        naive_sim
+            .borrow_mut()
+            .set_memory(2 * SECTOR_SIZE, &[0x00; SECTOR_SIZE as usize]);
+        block_on(naive.erase_sector(2)).unwrap();
+        for page in 0..SECTOR_SIZE / PAGE_SIZE {
+            let offset = (page * PAGE_SIZE) as usize;
+            block_on(naive.write(
+                2 * SECTOR_SIZE + offset as u32,
+                &data[offset..offset + PAGE_SIZE as usize],
+            ))
+            .unwrap();
+        }
+
+        let chip = sim.borrow();
+        let naive_chip = naive_sim.borrow();
+        let programs: std::vec::Vec<u8> = chip
+            .opcodes()
+            .into_iter()
+            .filter(|opcode| [0x21, 0x12].contains(opcode))
+            .collect();
+        assert_eq!(programs[0], 0x21);
+        assert_eq!(programs[1..], [0x12; 16]);
+        assert_eq!(
+            chip.memory(2 * SECTOR_SIZE, SECTOR_SIZE as usize),
+            naive_chip.memory(2 * SECTOR_SIZE, SECTOR_SIZE as usize)
+        );
+        assert_eq!(chip.transactions() + 16, naive_chip.transactions());
+    }
+
+    #[test]
+    fn update_region_crc_writes_a_validating_footer() {
+        let (sim, mut flash) = mock::flash();
+        let region: std::vec::Vec<u8> = (0..1000).map(|i| (i % 13) as u8).collect();
+        block_on(flash.write(0x8000, &region)).unwrap();
+        // An in-place update clearing bits of the region
+        block_on(flash.write(0x8000 + 500, &[0x00; 8])).unwrap();
+
+        let crc = block_on(flash.update_region_crc(0x8000, 1000, 0x8000 + 1000)).unwrap();
+
+        let updated = sim.borrow().memory(0x8000, 1000);
+        assert_eq!(crc, crc32::Crc32::checksum(&updated));
+        assert_eq!(sim.borrow().memory(0x8000 + 1000, 4), crc.to_le_bytes());
+        assert_eq!(block_on(flash.crc32(0x8000, 1000)).unwrap(), crc);
+    }
+
+    #[test]
+    fn reset_recover_waits_after_the_reset_opcodes() {
+        let (sim, mut flash) = mock::flash();
+        block_on(flash.enter_4_byte_address_mode()).unwrap();
+        sim.borrow_mut().log.clear();
+
+        let mut delay = mock::Delay(sim.clone());
+        block_on(flash.reset_recover(&mut delay)).unwrap();
+
+        let chip = sim.borrow();
+        let tail: std::vec::Vec<&mock::Event> = chip.log.iter().rev().take(3).collect();
+        assert!(matches!(tail[2], mock::Event::Transaction { tx, .. } if tx == &[0x66]));
+        assert!(matches!(tail[1], mock::Event::Transaction { tx, .. } if tx == &[0x99]));
+        assert_eq!(tail[0], &mock::Event::Delay(30_000));
+        // The reset restored the default address mode
+        assert_eq!(chip.status_registers[2] & 0x01, 0);
+    }
+
+    #[test]
+    fn read_reliable_retries_after_a_glitch() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().set_memory(0x9000, b"reliable");
+        sim.borrow_mut().read_faults.push(mock::ReadFault {
+            address: 0x9002,
+            xor: 0x20,
+            opcode: Some(0x13),
+            remaining: 1,
+        });
+
+        let mut buf = [0; 8];
+        block_on(flash.read_reliable(0x9000, &mut buf, 2)).unwrap();
+
+        assert_eq!(&buf, b"reliable");
+        // The glitched read and its comparison, then the retry and its comparison
+        assert_eq!(sim.borrow().count(0x13), 4);
+    }
+
+    #[test]
+    fn read_reliable_gives_up_after_the_retries() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().read_faults.push(mock::ReadFault {
+            address: 0x9002,
+            xor: 0x20,
+            opcode: Some(0x13),
+            remaining: 1,
+        });
+
+        let mut buf = [0; 8];
+        assert!(matches!(
+            block_on(flash.read_reliable(0x9000, &mut buf, 0)),
+            Err(Error::ReadbackFail)
+        ));
+        assert_eq!(sim.borrow().count(0x13), 2);
+    }
+
+    #[test]
+    fn read_jedec_id_decodes_the_id() {
+        let (sim, mut flash) = mock::flash();
+        let expected = JedecId {
+            manufacturer_id: 0xEF,
+            memory_type: 0x40,
+            capacity: 0x19,
+        };
+
+        assert_eq!(block_on(flash.read_jedec_id()).unwrap(), expected);
+        assert_eq!(flash.read_jedec_id_blocking().unwrap(), expected);
+        assert_eq!(sim.borrow().frames(), [[0x9F, 0, 0, 0], [0x9F, 0, 0, 0]]);
+
+        sim.borrow_mut().jedec_id = [0xC2, 0x20, 0x19];
+        assert_eq!(
+            block_on(flash.read_jedec_id()).unwrap().manufacturer_id,
+            0xC2
+        );
+    }
+
+    #[test]
+    fn provision_otp_spans_a_blob_into_the_second_register() {
+        let (sim, mut flash) = mock::flash();
+        let blob: std::vec::Vec<u8> = (0..300).map(|i| (i % 200) as u8).collect();
+
+        block_on(flash.provision_otp(&blob, true)).unwrap();
+
+        let chip = sim.borrow();
+        assert_eq!(chip.security_registers[0][..], blob[..256]);
+        assert_eq!(chip.security_registers[1][..44], blob[256..]);
+        assert_eq!(chip.security_registers[1][44..], [0xFF; 212]);
+        assert_eq!(chip.security_registers[2], [0xFF; 256]);
+
+        assert_eq!(
+            chip.commands(0x44),
+            [[0x44, 0x00, 0x10, 0x00], [0x44, 0x00, 0x20, 0x00]]
+        );
+        let programmed: std::vec::Vec<std::vec::Vec<u8>> = chip
+            .commands(0x42)
+            .iter()
+            .map(|frame| frame[..4].to_vec())
+            .collect();
+        assert_eq!(
+            programmed,
+            [[0x42, 0x00, 0x10, 0x00], [0x42, 0x00, 0x20, 0x00]]
+        );
+        // Only the registers that received data are locked
+        assert_eq!(chip.status_registers[1] & 0x38, 0x18);
+    }
+
+    #[test]
+    fn provision_otp_rejects_more_than_three_registers() {
+        let (sim, mut flash) = mock::flash();
+
+        assert!(matches!(
+            block_on(flash.provision_otp(&[0; 3 * SECURITY_REGISTER_SIZE as usize + 1], false)),
+            Err(Error::OutOfBounds)
+        ));
+        assert!(sim.borrow().log.is_empty());
+    }
+
+    #[test]
+    fn read_unique_id_follows_the_dummy_bytes() {
+        let (sim, mut flash) = mock::flash();
+        let id = [0xDE, 0xAD, 0xBE, 0xEF, 0x01, 0x02, 0x03, 0x04];
+        sim.borrow_mut().unique_id = id;
+
+        assert_eq!(block_on(flash.read_unique_id()).unwrap(), id);
+        assert!(matches!(
+            sim.borrow().log.last(),
+            Some(mock::Event::Transaction { tx, read_len: 8 }) if tx == &[0x4B, 0, 0, 0, 0]
+        ));
+
+        // One more dummy byte in 4-byte address mode
+        block_on(flash.enter_4_byte_address_mode()).unwrap();
+        assert_eq!(block_on(flash.read_unique_id()).unwrap(), id);
+    }
+
+    #[test]
+    fn sector_marked_written_reads_the_first_byte() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().set_memory(6 * SECTOR_SIZE, &[0xA5]);
+
+        assert!(block_on(flash.sector_marked_written(6, 0xA5)).unwrap());
+        assert!(!block_on(flash.sector_marked_written(7, 0xA5)).unwrap());
+
+        let chip = sim.borrow();
+        assert_eq!(chip.read_addresses(), [6 * SECTOR_SIZE, 7 * SECTOR_SIZE]);
+        assert!(chip
+            .log
+            .iter()
+            .all(|event| matches!(event, mock::Event::Transaction { read_len: 1, .. })));
+    }
+
+    #[test]
+    fn status_registers_2_and_3_are_readable() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().status_registers[1] = 0x42;
+        sim.borrow_mut().status_registers[2] = 0x61;
+
+        assert_eq!(block_on(flash.read_status_register_2()).unwrap(), 0x42);
+        assert_eq!(block_on(flash.read_status_register_3()).unwrap(), 0x61);
+        assert_eq!(sim.borrow().frames(), [[0x35, 0x00], [0x15, 0x00]]);
+    }
+
+    #[test]
+    fn bus_stability_check_passes_with_a_consistent_id() {
+        let (sim, mut flash) = mock::flash();
+
+        assert!(block_on(flash.bus_stability_check(5)).unwrap());
+        assert_eq!(sim.borrow().count(0x9F), 5);
+    }
+
+    #[test]
+    fn bus_stability_check_fails_when_the_id_varies() {
+        let (sim, mut flash) = mock::flash();
+        // The third read glitches
+        sim.borrow_mut().jedec_id_glitches = std::vec![JEDEC_ID, JEDEC_ID, [0xEF, 0x00, 0x19]];
+
+        assert!(!block_on(flash.bus_stability_check(5)).unwrap());
+        assert_eq!(sim.borrow().count(0x9F), 3);
+    }
+
+    #[test]
+    fn status_register_writes_enable_write_and_poll() {
+        let (sim, mut flash) = mock::flash();
+
+        block_on(flash.write_status_register_2(0x02)).unwrap();
+        block_on(flash.write_status_register_3(0x60)).unwrap();
+
+        let chip = sim.borrow();
+        assert_eq!(chip.status_registers[1..], [0x02, 0x60]);
+        let opcodes = chip.opcodes();
+        for (write, value) in [(0x31, 0x02), (0x11, 0x60)] {
+            let position = opcodes.iter().position(|&opcode| opcode == write).unwrap();
+            assert_eq!(opcodes[position - 2..position], [0x06, 0x05]);
+            assert_eq!(chip.commands(write), [[write, value]]);
+        }
+        assert!(!chip.is_busy());
+    }
+
+    #[test]
+    fn status_register_write_detects_a_readback_mismatch() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().ignore_status_register_writes = true;
+
+        assert!(matches!(
+            block_on(flash.write_status_register_1(0x1C)),
+            Err(Error::ReadbackFail)
+        ));
+        assert!(matches!(
+            block_on(flash.write_status_register_2(0x02)),
+            Err(Error::ReadbackFail)
+        ));
+        assert!(matches!(
+            block_on(flash.write_status_register_3(0x60)),
+            Err(Error::ReadbackFail)
+        ));
+
+        // Read-only bits are not compared
+        sim.borrow_mut().ignore_status_register_writes = false;
+        block_on(flash.write_status_register_2(0x82)).unwrap();
+    }
+
+    #[test]
+    fn volatile_status_register_writes_use_the_volatile_write_enable() {
+        let (sim, mut flash) = mock::flash();
+
+        block_on(flash.write_status_register_2_volatile(0x02)).unwrap();
+        block_on(flash.write_status_register_1_volatile(0x1C)).unwrap();
+        block_on(flash.write_status_register_3_volatile(0x60)).unwrap();
+
+        let chip = sim.borrow();
+        assert!(!chip.opcodes().contains(&0x06));
+        assert_eq!(chip.count(0x50), 3);
+        assert_eq!(chip.commands(0x01), [[0x01, 0x1C, 0x02]]);
+        assert_eq!(chip.commands(0x31), [[0x31, 0x02]]);
+        assert_eq!(chip.commands(0x11), [[0x11, 0x60]]);
+        for write in [0x01, 0x31, 0x11] {
+            let index = chip.opcodes().iter().position(|&op| op == write).unwrap();
+            assert_eq!(chip.opcodes()[index - 1], 0x50);
+        }
+        assert_eq!(chip.status_registers, [0x1C, 0x02, 0x60]);
+        drop(chip);
+
+        // The values don't survive a reset
+        block_on(flash.reset_recover(&mut mock::Delay(sim.clone()))).unwrap();
+        assert_eq!(sim.borrow().status_registers, [0x00; 3]);
+    }
+
+    #[test]
+    fn shared_bus_waits_between_status_polls() {
+        let (sim, flash) = mock::flash();
+        let mut flash = flash
+            .with_shared_bus(mock::Delay(sim.clone()), 500)
+            .unwrap();
+        assert_eq!(sim.borrow().log, [mock::Event::Hold(PinState::High)]);
+
+        sim.borrow_mut().log.clear();
+        sim.borrow_mut().set_busy(3);
+        block_on(flash.wait_ready()).unwrap();
+
+        // Every busy status poll is followed by the configured delay before the next transaction
+        let chip = sim.borrow();
+        let status = || mock::Event::Transaction {
+            tx: std::vec![0x05, 0x00],
+            read_len: 0,
+        };
+        let gap = || mock::Event::Delay(500);
+        assert_eq!(
+            chip.log,
+            [status(), gap(), status(), gap(), status(), gap(), status()]
+        );
+        drop(chip);
+
+        // Outside of shared bus mode, the status polls follow each other back to back
+        let (sim, mut flash) = mock::flash_with_delay(500);
+        sim.borrow_mut().set_busy(3);
+        block_on(flash.wait_ready()).unwrap();
+        assert_eq!(sim.borrow().count(0x05), 4);
+        assert!(sim.borrow().delays().is_empty());
+    }
+
+    #[test]
+    fn shared_bus_waits_between_transactions() {
+        let (sim, flash) = mock::flash();
+        let mut flash = flash
+            .with_shared_bus(mock::Delay(sim.clone()), 500)
+            .unwrap();
+        sim.borrow_mut().log.clear();
+
+        block_on(flash.write(0x80, &[0x55; 2 * PAGE_SIZE as usize])).unwrap();
+
+        // Polling a page program waits a fraction of its typical time instead, so the configured delay
+        // only appears before every page but the first
+        let chip = sim.borrow();
+        assert_eq!(chip.count(0x12), 3);
+        assert_eq!(chip.delays().iter().filter(|&&ns| ns == 500).count(), 2);
+        for pair in chip.log.windows(2) {
+            if let mock::Event::Delay(_) = pair[1] {
+                assert!(matches!(pair[0], mock::Event::Transaction { .. }));
+            }
+        }
+    }
+
+    #[test]
+    fn suspend_and_resume_an_erase() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().set_memory(SECTOR_SIZE, &[0xA5; 4]);
+        // An erase of sector 0 that is still running
+        sim.borrow_mut().set_busy(5);
+        assert!(!block_on(flash.is_suspended()).unwrap());
+
+        block_on(flash.suspend()).unwrap();
+        assert!(block_on(flash.is_suspended()).unwrap());
+        assert!(!sim.borrow().is_busy());
+
+        // A read of another sector goes ahead of the erase
+        let mut buf = [0; 4];
+        block_on(flash.read(SECTOR_SIZE, &mut buf)).unwrap();
+        assert_eq!(buf, [0xA5; 4]);
+
+        // A suspended operation can't be suspended again
+        block_on(flash.suspend()).unwrap();
+        assert!(block_on(flash.is_suspended()).unwrap());
+
+        sim.borrow_mut().log.clear();
+        block_on(flash.resume()).unwrap();
+        assert_eq!(sim.borrow().frames(), [[0x7A]]);
+        assert!(sim.borrow().is_busy());
+        assert!(!block_on(flash.is_suspended()).unwrap());
+
+        block_on(flash.wait_ready()).unwrap();
+        assert!(!sim.borrow().is_busy());
+    }
+
+    #[test]
+    fn suspend_and_resume_without_an_operation_are_ignored() {
+        let (sim, mut flash) = mock::flash();
+
+        block_on(flash.suspend()).unwrap();
+        assert!(!block_on(flash.is_suspended()).unwrap());
+        block_on(flash.resume()).unwrap();
+        assert!(!sim.borrow().is_busy());
+
+        assert_eq!(sim.borrow().opcodes(), [0x75, 0x05, 0x35, 0x7A]);
+    }
+
+    #[test]
+    fn recover_from_quad_precedes_a_single_line_jedec_read() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().continuous_read = true;
+
+        // The ID command is taken as an address while the chip is in continuous read mode
+        assert!(!block_on(flash.read_jedec_id()).unwrap().is_w25q256jv());
+
+        sim.borrow_mut().log.clear();
+        block_on(flash.recover_from_quad(&mut mock::Delay(sim.clone()))).unwrap();
+
+        let chip = sim.borrow();
+        assert!(!chip.continuous_read);
+        assert_eq!(chip.frames()[0], [0xFF, 0xFF]);
+        assert_eq!(chip.opcodes(), [0xFF, 0x05, 0x35, 0x66, 0x99, 0x9F]);
+        assert_eq!(chip.delays(), [30_000]);
+        // The reset delay is waited before the ID is read
+        let delay = chip
+            .log
+            .iter()
+            .position(|event| matches!(event, mock::Event::Delay(_)))
+            .unwrap();
+        assert!(
+            matches!(&chip.log[delay + 1], mock::Event::Transaction { tx, .. } if tx[0] == 0x9F)
+        );
+    }
+
+    #[test]
+    fn recover_from_quad_rejects_an_unexpected_id() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().continuous_read = true;
+        sim.borrow_mut().jedec_id = [0xEF, 0x40, 0x18];
+
+        assert!(matches!(
+            block_on(flash.recover_from_quad(&mut mock::Delay(sim.clone()))),
+            Err(Error::UnexpectedId { .. })
+        ));
+        assert!(!sim.borrow().continuous_read);
+    }
+
+    #[test]
+    fn power_down_and_release() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().set_memory(0x100, &[0x12, 0x34]);
+        let mut delay = mock::Delay(sim.clone());
+
+        block_on(flash.power_down(&mut delay)).unwrap();
+        assert!(sim.borrow().powered_down);
+        assert_eq!(sim.borrow().opcodes(), [0x05, 0xB9]);
+        assert_eq!(sim.borrow().delays(), [3_000]);
+
+        // Commands other than the release are ignored in power-down
+        let mut buf = [0; 2];
+        block_on(flash.read(0x100, &mut buf)).unwrap();
+        assert_eq!(buf, [0xFF; 2]);
+
+        sim.borrow_mut().log.clear();
+        block_on(flash.release_power_down(&mut delay)).unwrap();
+        assert!(!sim.borrow().powered_down);
+        // tRES1 is waited before the next command
+        assert_eq!(
+            sim.borrow().log,
+            [
+                mock::Event::Transaction {
+                    tx: std::vec![0xAB],
+                    read_len: 0,
+                },
+                mock::Event::Delay(3_000),
+            ]
+        );
+
+        block_on(flash.read(0x100, &mut buf)).unwrap();
+        assert_eq!(buf, [0x12, 0x34]);
+    }
+
+    #[test]
+    fn crc32_with_progress_reports_every_chunk() {
+        let (sim, mut flash) = mock::flash();
+        let data: std::vec::Vec<u8> = (0..200).map(|i| (i * 7) as u8).collect();
+        sim.borrow_mut().set_memory(0x1F0, &data);
+
+        let mut progress = std::vec::Vec::new();
+        let crc = block_on(flash.crc32_with_progress(0x1F0, 200, &mut |done| progress.push(done)))
+            .unwrap();
+
+        assert_eq!(progress, [64, 128, 192, 200]);
+        assert_eq!(crc, block_on(flash.crc32(0x1F0, 200)).unwrap());
+    }
+
+    #[test]
+    fn crc32_with_progress_matches_the_check_value() {
+        let (sim, mut flash) = mock::flash();
+        sim.borrow_mut().set_memory(0, b"123456789");
+
+        let mut calls = 0;
+        let crc = block_on(flash.crc32_with_progress(0, 9, &mut |done| {
+            calls += 1;
+            assert_eq!(done, 9);
+        }))
+        .unwrap();
+
+        assert_eq!(crc, 0xCBF43926);
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn write_limit_does_not_cut_short_the_sector_write_back() {
+        let (sim, mut flash) = mock::flash();
+        flash.set_auto_erase_on_write(true);
+        flash.set_max_write_len(16);
+        sim.borrow_mut().set_memory(2 * SECTOR_SIZE, &[0x0F; 16]);
+        sim.borrow_mut().set_memory(3 * SECTOR_SIZE - 4, &[0x3C; 4]);
+
+        block_on(NorFlash::write(&mut flash, 2 * SECTOR_SIZE + 4, &[0xF0; 4])).unwrap();
+
+        let chip = sim.borrow();
+        assert_eq!(chip.count(0x21), 1);
+        // The neighbouring data is written back after the erase
+        assert_eq!(chip.memory(2 * SECTOR_SIZE, 4), [0x0F; 4]);
+        assert_eq!(chip.memory(2 * SECTOR_SIZE + 4, 4), [0xF0; 4]);
+        assert_eq!(chip.memory(2 * SECTOR_SIZE + 8, 8), [0x0F; 8]);
+        assert_eq!(chip.memory(3 * SECTOR_SIZE - 4, 4), [0x3C; 4]);
+    }
+
+    #[test]
+    fn write_limit_is_checked_before_the_erase() {
+        let (sim, mut flash) = mock::flash();
+        flash.set_auto_erase_on_write(true);
+        flash.set_max_write_len(16);
+        sim.borrow_mut().set_memory(2 * SECTOR_SIZE, &[0x0F; 16]);
+
+        assert!(matches!(
+            block_on(NorFlash::write(&mut flash, 2 * SECTOR_SIZE, &[0xF0; 17])),
+            Err(Error::WriteTooLarge)
+        ));
+
+        // Nothing was erased or written
+        assert_eq!(sim.borrow().transactions(), 0);
+        assert_eq!(sim.borrow().memory(2 * SECTOR_SIZE, 16), [0x0F; 16]);
+    }
+
+    #[test]
+    fn write_record_interrupted_by_power_loss_reads_as_absent() {
+        let (sim, mut flash) = mock::flash();
+
+        // Power is lost after the first page of the data was programmed
+        sim.borrow_mut().power_loss_after = Some((0x12, 0));
+        assert!(block_on(flash.write_record(0x1000, &[0x5A; 300])).is_err());
+        assert_eq!(sim.borrow().memory(0x1000, 2), [0xFF; 2]);
+
+        let mut flash = restart(&sim);
+        let mut buf = [0; 300];
+        assert_eq!(block_on(flash.read_record(0x1000, &mut buf)).unwrap(), None);
+    }
+
+    #[test]
+    fn replace_sector_delays_only_between_page_programs() {
+        let (sim, mut flash) = mock::flash_with_delay(500);
+
+        block_on(flash.replace_sector(2, &[0x55; SECTOR_SIZE as usize])).unwrap();
+
+        let chip = sim.borrow();
+        let mut gaps = 0;
+        let mut programs = 0;
+        for event in &chip.log {
+            match event {
+                mock::Event::Delay(500) => gaps += 1,
+                mock::Event::Transaction { tx, .. } if tx[0] == 0x12 => {
+                    assert_eq!(gaps, programs, "one gap before every page but the first");
+                    programs += 1;
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(programs, 16);
+        assert_eq!(gaps, 15);
+    }
 }